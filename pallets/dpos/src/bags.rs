@@ -0,0 +1,204 @@
+//! A semi-sorted list of the validator candidates by their total backing, in the style of
+//! `pallet-bags-list`.
+//!
+//! Candidates are kept in bags of similar backing, delimited by [`Config::BagThresholds`]. Each bag
+//! is a doubly linked list, so moving a candidate to another bag when its backing changes takes a
+//! constant number of storage operations. Iteration goes from the bag with the most backing down,
+//! and within a bag in insertion order, unless candidates were reordered with
+//! [`CandidateList::put_in_front_of`].
+
+use crate::{BalanceOf, CandidateBags, CandidateNodes, Config, Error};
+use codec::{Decode, Encode, MaxEncodedLen};
+use frame_support::{
+	ensure, sp_runtime::traits::Bounded, traits::Get, CloneNoBound, DefaultNoBound,
+	PartialEqNoBound, RuntimeDebugNoBound,
+};
+use scale_info::TypeInfo;
+use sp_std::{iter, marker::PhantomData, prelude::*};
+
+/// A bag of candidates, holding the ends of its linked list.
+#[derive(
+	TypeInfo,
+	Encode,
+	Decode,
+	MaxEncodedLen,
+	CloneNoBound,
+	PartialEqNoBound,
+	RuntimeDebugNoBound,
+	DefaultNoBound,
+)]
+#[scale_info(skip_type_params(T))]
+pub struct Bag<T: Config> {
+	// The first candidate in the bag.
+	pub head: Option<T::AccountId>,
+	// The last candidate in the bag.
+	pub tail: Option<T::AccountId>,
+}
+
+/// The entry of a candidate in the list.
+#[derive(
+	TypeInfo, Encode, Decode, MaxEncodedLen, CloneNoBound, PartialEqNoBound, RuntimeDebugNoBound,
+)]
+#[scale_info(skip_type_params(T))]
+pub struct Node<T: Config> {
+	// The candidate before this one in its bag.
+	pub prev: Option<T::AccountId>,
+	// The candidate after this one in its bag.
+	pub next: Option<T::AccountId>,
+	// The upper threshold of the bag the candidate is in.
+	pub bag_upper: BalanceOf<T>,
+	// The total backing of the candidate.
+	pub score: BalanceOf<T>,
+}
+
+/// The list of candidates, stored in [`CandidateBags`] and [`CandidateNodes`].
+pub struct CandidateList<T>(PhantomData<T>);
+
+impl<T: Config> CandidateList<T> {
+	/// The upper threshold of the bag that candidates with `score` belong in.
+	pub fn notional_bag_for(score: BalanceOf<T>) -> BalanceOf<T> {
+		T::BagThresholds::get()
+			.iter()
+			.copied()
+			.find(|threshold| score <= *threshold)
+			.unwrap_or_else(BalanceOf::<T>::max_value)
+	}
+
+	/// Whether `who` is in the list.
+	pub fn contains(who: &T::AccountId) -> bool {
+		CandidateNodes::<T>::contains_key(who)
+	}
+
+	/// Put `who` into the list with `score`, removing it if `None`, and moving it to another bag
+	/// if its score calls for it.
+	pub fn set(who: &T::AccountId, score: Option<BalanceOf<T>>) {
+		let node = CandidateNodes::<T>::get(who);
+		match (node, score) {
+			(None, None) => {},
+			(Some(node), None) => Self::unlink(who, &node),
+			(None, Some(score)) => Self::insert(who, score),
+			(Some(mut node), Some(score)) =>
+				if node.bag_upper == Self::notional_bag_for(score) {
+					node.score = score;
+					CandidateNodes::<T>::insert(who, node);
+				} else {
+					Self::unlink(who, &node);
+					Self::insert(who, score);
+				},
+		}
+	}
+
+	/// Iterate over the candidates and their scores, from the bag with the highest scores down.
+	pub fn iter() -> impl Iterator<Item = (T::AccountId, BalanceOf<T>)> {
+		let thresholds = T::BagThresholds::get();
+		let max = BalanceOf::<T>::max_value();
+		let top = (thresholds.last() != Some(&max)).then_some(max);
+		top.into_iter()
+			.chain(thresholds.iter().rev().copied())
+			.filter_map(CandidateBags::<T>::get)
+			.flat_map(|bag| {
+				iter::successors(Self::entry(bag.head), |(_, node)| Self::entry(node.next.clone()))
+			})
+			.map(|(who, node)| (who, node.score))
+	}
+
+	/// Move `heavier` right in front of `lighter`, if they are in the same bag and `heavier` has
+	/// the higher score.
+	pub fn put_in_front_of(heavier: &T::AccountId, lighter: &T::AccountId) -> Result<(), Error<T>> {
+		let heavy = CandidateNodes::<T>::get(heavier).ok_or(Error::<T>::NotValidator)?;
+		let light = CandidateNodes::<T>::get(lighter).ok_or(Error::<T>::NotValidator)?;
+		ensure!(heavy.bag_upper == light.bag_upper, Error::<T>::NotInSameBag);
+		ensure!(heavy.score > light.score, Error::<T>::NotHeavier);
+
+		Self::unlink(heavier, &heavy);
+		// Unlinking may have changed the neighbours of `lighter`.
+		let light = CandidateNodes::<T>::get(lighter).ok_or(Error::<T>::NotValidator)?;
+		match light.prev.clone() {
+			Some(prev) => Self::mutate_node(&prev, |n| n.next = Some(heavier.clone())),
+			None => Self::mutate_bag(light.bag_upper, |bag| bag.head = Some(heavier.clone())),
+		}
+		Self::mutate_node(lighter, |n| n.prev = Some(heavier.clone()));
+		let node = Node {
+			prev: light.prev,
+			next: Some(lighter.clone()),
+			bag_upper: heavy.bag_upper,
+			score: heavy.score,
+		};
+		CandidateNodes::<T>::insert(heavier, node);
+		Ok(())
+	}
+
+	/// Append `who` to the tail of the bag for `score`.
+	fn insert(who: &T::AccountId, score: BalanceOf<T>) {
+		let bag_upper = Self::notional_bag_for(score);
+		let mut bag = CandidateBags::<T>::get(bag_upper).unwrap_or_default();
+		if let Some(tail) = bag.tail.as_ref() {
+			Self::mutate_node(tail, |n| n.next = Some(who.clone()));
+		}
+		let node = Node { prev: bag.tail.clone(), next: None, bag_upper, score };
+		CandidateNodes::<T>::insert(who, node);
+
+		bag.head.get_or_insert_with(|| who.clone());
+		bag.tail = Some(who.clone());
+		CandidateBags::<T>::insert(bag_upper, bag);
+	}
+
+	/// Remove `who`, whose entry is `node`, from the list.
+	fn unlink(who: &T::AccountId, node: &Node<T>) {
+		match node.prev.as_ref() {
+			Some(prev) => Self::mutate_node(prev, |n| n.next = node.next.clone()),
+			None => Self::mutate_bag(node.bag_upper, |bag| bag.head = node.next.clone()),
+		}
+		match node.next.as_ref() {
+			Some(next) => Self::mutate_node(next, |n| n.prev = node.prev.clone()),
+			None => Self::mutate_bag(node.bag_upper, |bag| bag.tail = node.prev.clone()),
+		}
+		CandidateNodes::<T>::remove(who);
+	}
+
+	fn entry(who: Option<T::AccountId>) -> Option<(T::AccountId, Node<T>)> {
+		let who = who?;
+		let node = CandidateNodes::<T>::get(&who)?;
+		Some((who, node))
+	}
+
+	fn mutate_node(who: &T::AccountId, f: impl FnOnce(&mut Node<T>)) {
+		CandidateNodes::<T>::mutate(who, |node| node.as_mut().map(f));
+	}
+
+	/// Mutate the bag at `bag_upper`, removing it once empty.
+	fn mutate_bag(bag_upper: BalanceOf<T>, f: impl FnOnce(&mut Bag<T>)) {
+		CandidateBags::<T>::mutate_exists(bag_upper, |bag| {
+			if let Some(inner) = bag.as_mut() {
+				f(inner);
+				if inner.head.is_none() {
+					*bag = None;
+				}
+			}
+		});
+	}
+
+	/// Check that the links of the list are consistent, and return the number of candidates in
+	/// it.
+	#[cfg(any(test, feature = "try-runtime"))]
+	pub(crate) fn do_try_state() -> usize {
+		let mut count = 0;
+		for (bag_upper, bag) in CandidateBags::<T>::iter() {
+			assert!(bag.head.is_some() && bag.tail.is_some(), "empty bags are removed");
+			let mut prev: Option<T::AccountId> = None;
+			let mut cursor = bag.head.clone();
+			while let Some(who) = cursor {
+				let node = CandidateNodes::<T>::get(&who).expect("linked nodes exist");
+				assert_eq!(node.prev, prev);
+				assert_eq!(node.bag_upper, bag_upper);
+				assert_eq!(Self::notional_bag_for(node.score), bag_upper);
+				count += 1;
+				prev = Some(who);
+				cursor = node.next;
+			}
+			assert_eq!(prev, bag.tail);
+		}
+		assert_eq!(count, CandidateNodes::<T>::iter().count(), "every node is in a bag");
+		count
+	}
+}
@@ -0,0 +1,166 @@
+//! Strategies to elect the validator set out of the validator candidates and the stake backing
+//! them.
+
+use codec::{Decode, Encode};
+use scale_info::TypeInfo;
+use sp_npos_elections::{
+	assignment_ratio_to_staked_normalized, to_support_map, BalancingConfig, ElectionResult,
+	IdentifierT, PerThing128, VoteWeight,
+};
+use sp_runtime::{traits::Get, Perbill, RuntimeDebug};
+use sp_std::{cmp::Reverse, collections::btree_map::BTreeMap, marker::PhantomData, prelude::*};
+
+pub use sp_npos_elections::{
+	ElectionScore, Error as ElectionError, EvaluateSupport, Support, Supports,
+};
+
+/// A voter in an election: its account, its stake, and the candidates it backs.
+pub type Voter<AccountId> = (AccountId, VoteWeight, Vec<AccountId>);
+
+/// How the stake of voters is distributed over the winners they back.
+pub type Assignments<AccountId> = Vec<(AccountId, Vec<(AccountId, VoteWeight)>)>;
+
+/// The input of an election, frozen for the offchain workers to compute a solution on.
+#[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct ElectionSnapshot<AccountId> {
+	/// The epoch the election is for.
+	pub epoch: u32,
+	/// The candidates to elect from.
+	pub candidates: Vec<AccountId>,
+	/// The backing of each candidate when the snapshot started, which the fallback elects by.
+	pub backing: Vec<VoteWeight>,
+	/// The voters, including the self vote of every candidate. Delegators only vote with their
+	/// counted nominations.
+	pub voters: Vec<Voter<AccountId>>,
+}
+
+/// A solution to the election of an [`ElectionSnapshot`], referring to candidates and voters by
+/// their index in it.
+#[derive(Encode, Decode, TypeInfo, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct RawSolution {
+	/// The epoch of the snapshot this solution is for.
+	pub epoch: u32,
+	/// The elected candidates, in the order they were elected in.
+	pub winners: Vec<u32>,
+	/// For each voter, in increasing order, how its stake is distributed over the winners it
+	/// backs.
+	pub assignments: Assignments<u32>,
+	/// The score of the supports that `assignments` add up to.
+	pub score: ElectionScore,
+}
+
+/// A way to elect the validator set.
+pub trait ElectionStrategy<AccountId> {
+	/// Elect at most `to_elect` of `candidates`, and distribute the stake of `voters` over the
+	/// winners.
+	///
+	/// The self stake of a candidate is passed in as a voter backing only that candidate. Winners
+	/// are returned in the order they were elected in, along with the stake backing them.
+	fn elect(
+		to_elect: usize,
+		candidates: Vec<AccountId>,
+		voters: Vec<Voter<AccountId>>,
+	) -> Result<Supports<AccountId>, ElectionError>;
+}
+
+/// Elect the candidates with the most stake backing them.
+///
+/// Voters back each of their candidates with their whole stake. Ties are won by whoever comes
+/// first in `candidates`.
+pub struct TopBacking;
+
+impl<AccountId: IdentifierT> ElectionStrategy<AccountId> for TopBacking {
+	fn elect(
+		to_elect: usize,
+		candidates: Vec<AccountId>,
+		voters: Vec<Voter<AccountId>>,
+	) -> Result<Supports<AccountId>, ElectionError> {
+		let index: BTreeMap<_, _> =
+			candidates.iter().enumerate().map(|(i, who)| (who.clone(), i)).collect();
+		let mut supports: Supports<AccountId> =
+			candidates.into_iter().map(|who| (who, Support::default())).collect();
+
+		for (who, stake, targets) in voters {
+			for target in targets {
+				if let Some(&i) = index.get(&target) {
+					let support = &mut supports[i].1;
+					support.total = support.total.saturating_add(stake.into());
+					support.voters.push((who.clone(), stake.into()));
+				}
+			}
+		}
+
+		// A stable sort, so ties keep their order.
+		supports.sort_by_key(|(_, support)| Reverse(support.total));
+		supports.truncate(to_elect);
+		Ok(supports)
+	}
+}
+
+/// Elect candidates with sequential Phragmén.
+///
+/// The stake of each voter is spread over the winners it backs, so that a large voter backing many
+/// candidates does not crowd out the candidates of smaller voters. The result is optionally
+/// balanced further according to `B`.
+pub struct SequentialPhragmen<P = Perbill, B = ()>(PhantomData<(P, B)>);
+
+impl<AccountId: IdentifierT, P: PerThing128, B: Get<Option<BalancingConfig>>>
+	ElectionStrategy<AccountId> for SequentialPhragmen<P, B>
+{
+	fn elect(
+		to_elect: usize,
+		candidates: Vec<AccountId>,
+		voters: Vec<Voter<AccountId>>,
+	) -> Result<Supports<AccountId>, ElectionError> {
+		let stakes = stakes_of(&voters);
+		let result =
+			sp_npos_elections::seq_phragmen::<_, P>(to_elect, candidates, voters, B::get())?;
+		into_supports(result, &stakes)
+	}
+}
+
+/// Elect candidates with PhragMMS.
+///
+/// Like [`SequentialPhragmen`], but each round balances the stake of the voters over the winners
+/// so far. This is slower, but gives a better minimum backing among the winners.
+pub struct PhragMMS<P = Perbill, B = ()>(PhantomData<(P, B)>);
+
+impl<AccountId: IdentifierT, P: PerThing128, B: Get<Option<BalancingConfig>>>
+	ElectionStrategy<AccountId> for PhragMMS<P, B>
+{
+	fn elect(
+		to_elect: usize,
+		candidates: Vec<AccountId>,
+		voters: Vec<Voter<AccountId>>,
+	) -> Result<Supports<AccountId>, ElectionError> {
+		let stakes = stakes_of(&voters);
+		let result = sp_npos_elections::phragmms::<_, P>(to_elect, candidates, voters, B::get())?;
+		into_supports(result, &stakes)
+	}
+}
+
+fn stakes_of<AccountId: IdentifierT>(
+	voters: &[Voter<AccountId>],
+) -> BTreeMap<AccountId, VoteWeight> {
+	voters.iter().map(|(who, stake, _)| (who.clone(), *stake)).collect()
+}
+
+/// Turn the assignments of `result` into the supports of its winners, in the order they were
+/// elected in.
+fn into_supports<AccountId: IdentifierT, P: PerThing128>(
+	result: ElectionResult<AccountId, P>,
+	stakes: &BTreeMap<AccountId, VoteWeight>,
+) -> Result<Supports<AccountId>, ElectionError> {
+	let staked = assignment_ratio_to_staked_normalized(result.assignments, |who| {
+		stakes.get(who).copied().unwrap_or_default()
+	})?;
+	let mut supports = to_support_map(&staked);
+	Ok(result
+		.winners
+		.into_iter()
+		.map(|(who, _)| {
+			let support = supports.remove(&who).unwrap_or_default();
+			(who, support)
+		})
+		.collect())
+}
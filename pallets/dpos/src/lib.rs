@@ -2,6 +2,12 @@
 
 pub use pallet::*;
 
+pub mod bags;
+pub mod election;
+pub mod migrations;
+pub mod payout;
+pub mod session;
+
 #[cfg(test)]
 mod mock;
 
@@ -15,29 +21,112 @@ mod benchmarking;
 // https://paritytech.github.io/polkadot-sdk/master/polkadot_sdk_docs/guides/your_first_pallet/index.html
 // https://paritytech.github.io/polkadot-sdk/master/frame_support/attr.pallet.html#dev-mode-palletdev_mode
 #[frame_support::pallet(dev_mode)]
+// The code generated for calls returning `DispatchResultWithPostInfo` converts their result into
+// itself.
+#[allow(clippy::useless_conversion)]
 pub mod pallet {
+	use crate::{
+		bags::{Bag, CandidateList, Node},
+		election::{
+			Assignments, ElectionScore, ElectionSnapshot, ElectionStrategy, EvaluateSupport,
+			RawSolution, Support, Supports, Voter,
+		},
+		payout::EraPayout,
+	};
 	use frame_support::{
 		pallet_prelude::*,
-		sp_runtime::traits::Zero,
-		traits::{fungible, FindAuthor},
+		sp_runtime::{
+			helpers_128bit::multiply_by_rational_with_rounding,
+			traits::{Hash, One, SaturatedConversion, Saturating, TrailingZeroInput, Zero},
+			ConsensusEngineId, FixedPointNumber, FixedU128, Perbill, Rounding,
+		},
+		traits::{
+			fungible::{self, Balanced, BalancedHold, Inspect, Mutate, MutateHold},
+			tokens::Precision,
+			FindAuthor, Imbalance, OnUnbalanced, Randomness, ValidatorRegistration,
+		},
+		CloneNoBound, DefaultNoBound, PartialEqNoBound, RuntimeDebugNoBound,
+	};
+	use frame_system::{
+		offchain::{SendTransactionTypes, SubmitTransaction},
+		pallet_prelude::*,
+	};
+	use sp_npos_elections::VoteWeight;
+	use sp_runtime::offchain::storage::StorageValueRef;
+	use sp_std::{
+		cmp::Reverse,
+		collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+		prelude::*,
 	};
-	use frame_system::pallet_prelude::*;
-	use sp_std::prelude::*;
 
 	pub trait ReportNewValidatorSet<AccountId> {
 		fn report_new_validator_set(_new_set: Vec<AccountId>) {}
 	}
 
+	impl<AccountId> ReportNewValidatorSet<AccountId> for () {}
+
+	/// A [`FindAuthor`] that follows the slot schedule of the [`ActiveValidators`], one slot per
+	/// block. See [`Pallet::slot_author`].
+	///
+	/// Every block is taken to be authored by the validator of its slot, so nobody ever misses a
+	/// slot. It suits chains whose block production does not name the author, such as a node
+	/// without consensus.
+	pub struct SlotAuthor<T>(PhantomData<T>);
+
+	impl<T: Config> FindAuthor<T::AccountId> for SlotAuthor<T> {
+		fn find_author<'a, I>(_digests: I) -> Option<T::AccountId>
+		where
+			I: 'a + IntoIterator<Item = (ConsensusEngineId, &'a [u8])>,
+		{
+			Pallet::<T>::slot_author(frame_system::Pallet::<T>::block_number())
+		}
+	}
+
+	/// The engine id of the pre-runtime digest naming the author of a block.
+	pub const DPOS_ENGINE_ID: ConsensusEngineId = *b"dpos";
+
+	/// A [`FindAuthor`] that reads the author of a block from its [`DPOS_ENGINE_ID`] pre-runtime
+	/// digest, which holds the index of the author in the [`ActiveValidators`] as a `u32`.
+	///
+	/// Blocks without such a digest have no known author, so nobody is rewarded for them, and
+	/// they count as neither authored nor missed. Block authoring has to add the digest, and as
+	/// nothing here checks it, a named author that is not the one of the slot is not rewarded.
+	pub struct DigestAuthor<T>(PhantomData<T>);
+
+	impl<T: Config> FindAuthor<T::AccountId> for DigestAuthor<T> {
+		fn find_author<'a, I>(digests: I) -> Option<T::AccountId>
+		where
+			I: 'a + IntoIterator<Item = (ConsensusEngineId, &'a [u8])>,
+		{
+			let (_, mut data) = digests.into_iter().find(|(id, _)| *id == DPOS_ENGINE_ID)?;
+			let index = u32::decode(&mut data).ok()?;
+			ActiveValidators::<T>::get().get(index as usize).cloned()
+		}
+	}
+
+	/// The index of an epoch, incremented every [`Config::EpochDuration`] blocks.
+	pub type EpochIndex = u32;
+
 	pub type BalanceOf<T> = <<T as Config>::NativeBalance as fungible::Inspect<
 		<T as frame_system::Config>::AccountId,
 	>>::Balance;
 
+	/// The in-code storage version.
+	const STORAGE_VERSION: StorageVersion = StorageVersion::new(5);
+
+	/// Offchain storage key of the last epoch this node mined an election solution for.
+	const OFFCHAIN_LAST_MINED: &[u8] = b"pallet-dpos::election::last-mined";
+
+	/// The weight of checking one edge, a voter backing a winner, of a submitted solution.
+	const SOLUTION_EDGE_WEIGHT: Weight = Weight::from_parts(1_000_000, 0);
+
 	#[pallet::pallet]
+	#[pallet::storage_version(STORAGE_VERSION)]
 	pub struct Pallet<T>(_);
 
 	/// Configure the pallet by specifying the parameters and types on which it depends.
 	#[pallet::config]
-	pub trait Config: frame_system::Config {
+	pub trait Config: frame_system::Config + SendTransactionTypes<Call<Self>> {
 		/// Because this pallet emits events, it depends on the runtime's definition of an event.
 		/// https://paritytech.github.io/polkadot-sdk/master/polkadot_sdk_docs/reference_docs/frame_runtime_types/index.html
 		type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
@@ -46,27 +135,186 @@ pub mod pallet {
 		type NativeBalance: fungible::Inspect<Self::AccountId>
 			+ fungible::Mutate<Self::AccountId>
 			+ fungible::hold::Inspect<Self::AccountId>
-			+ fungible::hold::Mutate<Self::AccountId>
+			+ fungible::hold::Mutate<Self::AccountId, Reason = Self::RuntimeHoldReason>
+			+ fungible::hold::Balanced<Self::AccountId>
 			+ fungible::freeze::Inspect<Self::AccountId>
 			+ fungible::freeze::Mutate<Self::AccountId>;
 
+		/// Overarching hold reason. Our `HoldReason` below will become a part of this "Outer Enum"
+		/// thanks to the `#[runtime]` macro.
+		type RuntimeHoldReason: From<HoldReason>;
+
 		/// The maximum number of authorities that the pallet can hold.
 		type MaxValidators: Get<u32>;
 
-		/// Find the author of a block. A fake provide for this type is provided in the runtime. You
-		/// can use a similar mechanism in your tests.
+		/// Find the author of a block, given its pre-runtime digests. See [`DigestAuthor`] for one
+		/// that reads it from a digest of this pallet, and [`SlotAuthor`] for one that follows the
+		/// slot schedule. You can use a fake one in your tests.
+		///
+		/// Only an author that matches the slot schedule is rewarded.
 		type FindAuthor: FindAuthor<Self::AccountId>;
 
+		/// The source of randomness seeding the slot schedule of each epoch. See
+		/// [`Pallet::slot_author`].
+		type Randomness: Randomness<Self::Hash, BlockNumberFor<Self>>;
+
+		/// Whether slots are assigned to the active validators at random, in proportion to their
+		/// backing, rather than round-robin.
+		#[pallet::constant]
+		type StakeWeightedSlots: Get<bool>;
+
 		/// Report the new validators to the runtime. This is done through a custom trait defined in
 		/// this pallet.
 		type ReportNewValidatorSet: ReportNewValidatorSet<Self::AccountId>;
 
+		/// Whether the validators of an election only become active at the start of the epoch
+		/// after, in [`QueuedValidators`] until then.
+		///
+		/// Set this when a session pallet rotating along with the epochs is the one to switch
+		/// validators, as it queues the sets of its [`pallet_session::SessionManager`] for a
+		/// session before activating them. The [`ActiveValidators`] then are the ones it runs with.
+		#[pallet::constant]
+		type ElectAhead: Get<bool>;
+
+		/// Whether a candidate registered what it needs to author blocks, such as session keys.
+		/// Only registered candidates are elected.
+		type ValidatorRegistration: ValidatorRegistration<Self::AccountId>;
+
 		/// We use a configurable constant `BlockNumber` to tell us when we should trigger the
 		/// validator set change. The runtime developer should implement this to represent the time
 		/// they want validators to change, but for your pallet, you just care about the block
 		/// number.
 		#[pallet::constant]
 		type EpochDuration: Get<BlockNumberFor<Self>>;
+
+		/// The amount minted every block as a reward for the block author and its delegators, on
+		/// top of the [`Config::EraPayout`].
+		#[pallet::constant]
+		type BlockReward: Get<BalanceOf<Self>>;
+
+		/// The payout of every epoch, depending on how much of the issuance backed the validators.
+		/// See [`crate::payout`] for the available curves.
+		type EraPayout: EraPayout<BalanceOf<Self>>;
+
+		/// Handler for the remainder of the [`Config::EraPayout`], for instance a treasury
+		/// account. `()` burns it, or rather never mints it.
+		type RewardRemainder: OnUnbalanced<fungible::Credit<Self::AccountId, Self::NativeBalance>>;
+
+		/// The most a validator can move its commission, up or down, within a single epoch.
+		#[pallet::constant]
+		type MaxCommissionChange: Get<Perbill>;
+
+		/// The least self stake a validator candidate can register with.
+		#[pallet::constant]
+		type MinValidatorBond: Get<BalanceOf<Self>>;
+
+		/// The least amount a delegation can hold. Delegations below it, for instance after a
+		/// slash, can be reaped by anyone with `reap_dust_delegation`.
+		#[pallet::constant]
+		type MinDelegation: Get<BalanceOf<Self>>;
+
+		/// The deposit held from delegators for as long as their delegation is stored.
+		#[pallet::constant]
+		type DelegationDeposit: Get<BalanceOf<Self>>;
+
+		/// The most delegators counted towards the backing and rewards of a validator. Other
+		/// delegators of the validator are oversubscribed.
+		#[pallet::constant]
+		type MaxDelegatorsPerValidator: Get<u32>;
+
+		/// The number of epochs withdrawn stake stays held, and slashable, before it can be
+		/// released with `withdraw_unbonded`.
+		#[pallet::constant]
+		type UnbondingPeriod: Get<EpochIndex>;
+
+		/// The maximum number of unlocking chunks an account can have at once.
+		#[pallet::constant]
+		type MaxUnlockingChunks: Get<u32>;
+
+		/// The maximum number of delegations between a delegator and the validator it ends up
+		/// backing. `1` only allows delegating to validators directly.
+		#[pallet::constant]
+		type MaxDelegationDepth: Get<u32>;
+
+		/// The maximum number of validators a single delegation can nominate.
+		#[pallet::constant]
+		type MaxNominations: Get<u32>;
+
+		/// How offchain workers elect the validator set out of the snapshot of the top of the
+		/// [`CandidateList`]. See [`crate::election`] for the available strategies.
+		type ElectionStrategy: ElectionStrategy<Self::AccountId>;
+
+		/// The number of blocks at the end of every epoch in which offchain workers can submit
+		/// election solutions. `0` disables offchain elections, so the fallback of electing the
+		/// candidates with the most backing is always used.
+		#[pallet::constant]
+		type UnsignedPhase: Get<BlockNumberFor<Self>>;
+
+		/// The number of blocks right before the unsigned phase in which the election input is
+		/// snapshotted, one page per block.
+		#[pallet::constant]
+		type SnapshotPages: Get<u32>;
+
+		/// The number of delegations visited for each page of the snapshot. There can be at most
+		/// `SnapshotPages * VotersPerPage` delegators, so that all of them take part in elections.
+		#[pallet::constant]
+		type VotersPerPage: Get<u32>;
+
+		/// The maximum number of candidates, taken from the top of the [`CandidateList`], that an
+		/// election picks from.
+		#[pallet::constant]
+		type MaxElectableCandidates: Get<u32>;
+
+		/// The upper thresholds of the bags of the [`CandidateList`], in increasing order.
+		/// Candidates with more backing than the last threshold go into a bag of their own.
+		#[pallet::constant]
+		type BagThresholds: Get<&'static [BalanceOf<Self>]>;
+
+		/// The base priority of the unsigned transactions submitting election solutions.
+		#[pallet::constant]
+		type UnsignedPriority: Get<TransactionPriority>;
+
+		/// Handler for the funds slashed from validators and their delegators. `()` burns them.
+		type OnSlash: OnUnbalanced<fungible::Credit<Self::AccountId, Self::NativeBalance>>;
+
+		/// The share of its slots in an epoch that an active validator may miss. Missing more is
+		/// an offence, for which the validator is chilled and slashed. `Perbill::one()` disables
+		/// offences.
+		#[pallet::constant]
+		type MissedSlotsThreshold: Get<Perbill>;
+
+		/// The fraction slashed from an offender that missed all of its slots in the epoch.
+		/// Offenders that missed fewer slots are slashed proportionally less.
+		#[pallet::constant]
+		type MissedSlotsSlash: Get<Perbill>;
+
+		/// The number of full epochs a slash is deferred for after the offence, during which
+		/// [`Config::SlashCancelOrigin`] can cancel it. Zero slashes right away.
+		#[pallet::constant]
+		type SlashDeferDuration: Get<EpochIndex>;
+
+		/// The origin that can cancel deferred slashes.
+		type SlashCancelOrigin: EnsureOrigin<Self::RuntimeOrigin>;
+
+		/// The number of epochs the [`Exposures`] of the validators and their unclaimed
+		/// [`EpochRewards`] are kept for. Has to be longer than [`Config::SlashDeferDuration`], so
+		/// that deferred slashes still find the exposure of the offence.
+		#[pallet::constant]
+		type HistoryDepth: Get<EpochIndex>;
+	}
+
+	/// A reason for the pallet placing a hold on funds.
+	#[pallet::composite_enum]
+	pub enum HoldReason {
+		/// Funds are held as the self-bond of a validator candidate.
+		#[codec(index = 0)]
+		ValidatorBond,
+		/// Funds are held as a delegation towards a validator candidate.
+		#[codec(index = 1)]
+		Delegation,
+		/// Funds are held as the storage deposit of a delegation.
+		#[codec(index = 2)]
+		DelegationDeposit,
 	}
 
 	/// The pallet's storage items.
@@ -79,39 +327,363 @@ pub mod pallet {
 
 	/// Take note of the different attributes needed on a custom structure so that it can be used in
 	/// storage. You can add other derives if you need...
-	#[derive(TypeInfo, Encode, Decode, MaxEncodedLen)]
+	#[derive(
+		TypeInfo, Encode, Decode, MaxEncodedLen, CloneNoBound, PartialEqNoBound, RuntimeDebugNoBound,
+	)]
 	#[scale_info(skip_type_params(T))]
 	pub struct DelegationInfo<T: Config> {
-		// The validator who is getting the delegation
-		pub who: T::AccountId,
+		// The accounts nominated by the delegation: either validators, or a single other
+		// delegator whose validators are backed instead.
+		pub targets: BoundedVec<T::AccountId, T::MaxNominations>,
 		// The amount being delegated
 		pub amount: BalanceOf<T>,
+		// How `amount` is spread over the validators backed by the delegation. It is split evenly
+		// when nominating, then spread the way each election distributes it.
+		pub nominations: BoundedVec<Nomination<T>, T::MaxNominations>,
+	}
+
+	impl<T: Config> DelegationInfo<T> {
+		/// The validators backed by this delegation.
+		pub fn validators(&self) -> Vec<T::AccountId> {
+			self.nominations.iter().map(|n| n.validator.clone()).collect()
+		}
+	}
+
+	/// The part of a delegation backing one of its validators.
+	///
+	/// Elections are free to spread the stake of a delegation over its validators differently,
+	/// this split is what backing, rewards and slashes are based on.
+	#[derive(
+		TypeInfo, Encode, Decode, MaxEncodedLen, CloneNoBound, PartialEqNoBound, RuntimeDebugNoBound,
+	)]
+	#[scale_info(skip_type_params(T))]
+	pub struct Nomination<T: Config> {
+		// The validator being backed.
+		pub validator: T::AccountId,
+		// The part of the delegated amount backing `validator`.
+		pub stake: BalanceOf<T>,
+		// The `reward_per_share` of the validator when the rewards of this nomination were last
+		// settled.
+		pub reward_per_share_paid: FixedU128,
+	}
+
+	/// The delegation of each delegator, held under `HoldReason::Delegation`.
+	#[pallet::storage]
+	pub type Delegations<T: Config> =
+		CountedStorageMap<Key = T::AccountId, Value = DelegationInfo<T>>;
+
+	/// The [`Exposure`] of every validator active in each of the last [`Config::HistoryDepth`]
+	/// epochs. Payouts and slashes for an epoch are based on it.
+	#[pallet::storage]
+	pub type Exposures<T: Config> =
+		StorageDoubleMap<Key1 = EpochIndex, Key2 = T::AccountId, Value = Exposure<T>>;
+
+	/// The share of the [`Config::EraPayout`] of each of the last [`Config::HistoryDepth`] epochs
+	/// earned by each validator, until it is paid out with `payout_stakers`.
+	#[pallet::storage]
+	pub type EpochRewards<T: Config> =
+		StorageDoubleMap<Key1 = EpochIndex, Key2 = T::AccountId, Value = BalanceOf<T>>;
+
+	/// Where the rewards of each validator and delegator go, if not into their free balance.
+	#[pallet::storage]
+	pub type Payees<T: Config> = StorageMap<
+		Key = T::AccountId,
+		Value = RewardDestination<T::AccountId>,
+		QueryKind = ValueQuery,
+	>;
+
+	/// The commission of a validator at the start of the last epoch in which it changed it.
+	///
+	/// All commission changes within one epoch are bounded relative to this value.
+	#[pallet::storage]
+	pub type CommissionBaseline<T: Config> =
+		StorageMap<Key = T::AccountId, Value = (EpochIndex, Perbill)>;
+
+	/// Some stake that was withdrawn from `validator`, and is waiting to be released.
+	#[derive(
+		TypeInfo, Encode, Decode, MaxEncodedLen, CloneNoBound, PartialEqNoBound, RuntimeDebugNoBound,
+	)]
+	#[scale_info(skip_type_params(T))]
+	pub struct UnlockChunk<T: Config> {
+		// The validator this stake was backing. If this is the owner of the chunk itself, this was
+		// its self stake.
+		pub validator: T::AccountId,
+		// The amount that is unlocking.
+		pub value: BalanceOf<T>,
+		// The epoch from which on the chunk can be withdrawn.
+		pub unlock_epoch: EpochIndex,
+	}
+
+	/// The stake of each account that is unbonding. It is still held, and can still be slashed.
+	#[pallet::storage]
+	pub type Unlocking<T: Config> = StorageMap<
+		Key = T::AccountId,
+		Value = BoundedVec<UnlockChunk<T>, T::MaxUnlockingChunks>,
+		QueryKind = ValueQuery,
+	>;
+
+	/// Reverse index of [`Unlocking`], listing the accounts with stake unbonding from each
+	/// validator, including the validator itself.
+	#[pallet::storage]
+	pub type UnbondingFrom<T: Config> =
+		StorageDoubleMap<Key1 = T::AccountId, Key2 = T::AccountId, Value = ()>;
+
+	/// Reverse index of [`Delegations`], listing the delegators of each validator, including the
+	/// ones delegating through other delegators.
+	#[pallet::storage]
+	pub type DelegatorsOf<T: Config> =
+		StorageDoubleMap<Key1 = T::AccountId, Key2 = T::AccountId, Value = ()>;
+
+	/// The delegators counted towards the backing of each validator, with the stake of their
+	/// nomination of it. Only their stake earns rewards, and can be slashed.
+	///
+	/// When full, a new nomination replaces the smallest counted one if it has more stake, and is
+	/// oversubscribed otherwise. Oversubscribed delegators are not counted in when a slot frees
+	/// up, but they can nominate or top up their delegation to try again.
+	#[pallet::storage]
+	pub type CountedDelegators<T: Config> = StorageMap<
+		Key = T::AccountId,
+		Value = BoundedVec<(T::AccountId, BalanceOf<T>), T::MaxDelegatorsPerValidator>,
+		QueryKind = ValueQuery,
+	>;
+
+	/// Reverse index of [`Delegations`] made to other delegators, listing the delegators who
+	/// delegate through each delegator.
+	///
+	/// A delegator that others delegate through cannot leave or nominate other validators, so
+	/// that the validators backed by a chain never change.
+	#[pallet::storage]
+	pub type ChainedDelegators<T: Config> =
+		StorageDoubleMap<Key1 = T::AccountId, Key2 = T::AccountId, Value = ()>;
+
+	/// The record we keep for every registered validator candidate.
+	#[derive(
+		TypeInfo,
+		Encode,
+		Decode,
+		MaxEncodedLen,
+		CloneNoBound,
+		DefaultNoBound,
+		PartialEqNoBound,
+		RuntimeDebugNoBound,
+	)]
+	#[scale_info(skip_type_params(T))]
+	pub struct ValidatorInfo<T: Config> {
+		// The amount the validator has bonded themselves, held under `HoldReason::ValidatorBond`.
+		pub self_stake: BalanceOf<T>,
+		// The self stake plus the stake of every nomination of this validator.
+		pub total_backing: BalanceOf<T>,
+		// The rewards accrued so far by each unit delegated to this validator. Only ever grows.
+		pub reward_per_share: FixedU128,
+		// The cut of each block reward the validator takes before splitting it with delegators.
+		pub commission: Perbill,
+	}
+
+	impl<T: Config> ValidatorInfo<T> {
+		/// A validator without self stake has unregistered, and is no longer a candidate.
+		pub fn is_candidate(&self) -> bool {
+			!self.self_stake.is_zero()
+		}
+	}
+
+	/// Whether a validator candidate takes part in elections.
+	#[derive(TypeInfo, Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+	pub enum ValidatorStatus {
+		/// The candidate is in the [`CandidateList`], up for election.
+		Validating,
+		/// The candidate is [`Chilled`], out of elections.
+		Chilled,
+	}
+
+	/// What was at stake behind a validator during an epoch, as snapshotted when it started.
+	#[derive(
+		TypeInfo, Encode, Decode, MaxEncodedLen, CloneNoBound, PartialEqNoBound, RuntimeDebugNoBound,
+	)]
+	#[scale_info(skip_type_params(T))]
+	pub struct Exposure<T: Config> {
+		// The self stake of the validator.
+		pub own: BalanceOf<T>,
+		// The self stake and the stake of the counted delegators.
+		pub total: BalanceOf<T>,
+		// The commission of the validator.
+		pub commission: Perbill,
+		// The counted delegators of the validator, with their stake.
+		pub others: BoundedVec<(T::AccountId, BalanceOf<T>), T::MaxDelegatorsPerValidator>,
+	}
+
+	/// How much [`Pallet::slash_stake`] takes from each account with stake behind a validator.
+	pub(crate) enum SlashAmount<T: Config> {
+		/// The same fraction of every stake.
+		Fraction(Perbill),
+		/// Up to the given amount from each account, out of each of its stakes in turn.
+		Exposed(BTreeMap<T::AccountId, BalanceOf<T>>),
+	}
+
+	impl<T: Config> SlashAmount<T> {
+		/// The amount to slash from `account` out of one of its stakes, of `available`.
+		fn take(&mut self, account: &T::AccountId, available: BalanceOf<T>) -> BalanceOf<T> {
+			match self {
+				Self::Fraction(fraction) => fraction.mul_floor(available),
+				Self::Exposed(amounts) => amounts
+					.get_mut(account)
+					.map(|left| {
+						let amount = (*left).min(available);
+						left.saturating_reduce(amount);
+						amount
+					})
+					.unwrap_or_default(),
+			}
+		}
+	}
+
+	/// Where the rewards of a validator or a delegator go.
+	#[derive(
+		TypeInfo, Encode, Decode, MaxEncodedLen, Clone, PartialEq, Eq, RuntimeDebug, Default,
+	)]
+	pub enum RewardDestination<AccountId> {
+		/// Added to the stake that earned them: the self stake of a validator, or the nomination
+		/// of a delegator. Rewards earned from a validator that is no longer a candidate are paid
+		/// into the free balance instead.
+		Staked,
+		/// Paid into the free balance of the staker.
+		#[default]
+		Free,
+		/// Paid into the free balance of another account.
+		Account(AccountId),
+	}
+
+	/// A slash waiting in [`UnappliedSlashes`].
+	#[derive(
+		TypeInfo, Encode, Decode, MaxEncodedLen, CloneNoBound, PartialEqNoBound, RuntimeDebugNoBound,
+	)]
+	#[scale_info(skip_type_params(T))]
+	pub struct UnappliedSlash<T: Config> {
+		// The offending validator.
+		pub validator: T::AccountId,
+		// The fraction of the stake behind the validator to slash.
+		pub fraction: Perbill,
+		// The epoch of the offence.
+		pub epoch: EpochIndex,
+	}
+
+	/// The slots of an active validator in the current epoch.
+	#[derive(
+		TypeInfo, Encode, Decode, MaxEncodedLen, Clone, Copy, Default, PartialEq, Eq, RuntimeDebug,
+	)]
+	pub struct SlotRecord {
+		// The slots assigned to the validator.
+		pub expected: u32,
+		// The assigned slots in which the validator did author the block.
+		pub authored: u32,
+	}
+
+	/// The pool of validator candidates that the election picks from.
+	///
+	/// The record of a validator that unregistered is kept around until its last delegator has
+	/// left, so that its backing stays in sync if it registers again.
+	#[pallet::storage]
+	pub type Validators<T: Config> = StorageMap<Key = T::AccountId, Value = ValidatorInfo<T>>;
+
+	/// The bags of the [`CandidateList`], by their upper threshold.
+	#[pallet::storage]
+	pub type CandidateBags<T: Config> = StorageMap<Key = BalanceOf<T>, Value = Bag<T>>;
+
+	/// The entries of the [`CandidateList`], by candidate.
+	#[pallet::storage]
+	pub type CandidateNodes<T: Config> = StorageMap<Key = T::AccountId, Value = Node<T>>;
+
+	/// The validators of the current epoch, elected at its start, or at the start of the previous
+	/// one with [`Config::ElectAhead`].
+	#[pallet::storage]
+	pub type ActiveValidators<T: Config> =
+		StorageValue<Value = BoundedVec<T::AccountId, T::MaxValidators>, QueryKind = ValueQuery>;
+
+	/// The validators elected at the start of the current epoch, to become active at the start of
+	/// the next one. Only used with [`Config::ElectAhead`].
+	#[pallet::storage]
+	pub type QueuedValidators<T: Config> =
+		StorageValue<Value = BoundedVec<T::AccountId, T::MaxValidators>, QueryKind = ValueQuery>;
+
+	/// The session keys of the validators of the current session, kept up to date by
+	/// `pallet-session`.
+	#[pallet::storage]
+	pub type Authorities<T: Config> = StorageValue<
+		Value = BoundedVec<crate::session::AuthorityId, T::MaxValidators>,
+		QueryKind = ValueQuery,
+	>;
+
+	/// The slots of each active validator in the current epoch, checked for offences at its end.
+	#[pallet::storage]
+	pub type SlotsOf<T: Config> =
+		StorageMap<Key = T::AccountId, Value = SlotRecord, QueryKind = ValueQuery>;
+
+	/// The candidates taken out of elections, by themselves or after an offence, with the epoch
+	/// they were chilled in. They keep their bond and delegations, and stay chilled until they
+	/// validate again or unregister.
+	#[pallet::storage]
+	pub type Chilled<T: Config> = StorageMap<Key = T::AccountId, Value = EpochIndex>;
+
+	/// The deferred slashes, by the epoch at the start of which they are applied.
+	#[pallet::storage]
+	pub type UnappliedSlashes<T: Config> = StorageMap<
+		Key = EpochIndex,
+		Value = BoundedVec<UnappliedSlash<T>, T::MaxValidators>,
+		QueryKind = ValueQuery,
+	>;
+
+	/// The total backing of each of the [`ActiveValidators`] when they were elected, in the same
+	/// order.
+	#[pallet::storage]
+	pub type ActiveBacking<T: Config> =
+		StorageValue<Value = BoundedVec<BalanceOf<T>, T::MaxValidators>, QueryKind = ValueQuery>;
+
+	/// The random seed of the slot schedule of the current epoch.
+	#[pallet::storage]
+	pub type SlotSeed<T: Config> = StorageValue<Value = T::Hash, QueryKind = ValueQuery>;
+
+	/// The index of the current epoch.
+	#[pallet::storage]
+	pub type CurrentEpoch<T> = StorageValue<Value = EpochIndex, QueryKind = ValueQuery>;
+
+	/// How far the snapshot of the election at the end of the current epoch got.
+	#[derive(TypeInfo, Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+	pub enum SnapshotStatus {
+		/// `pages` pages were taken so far, the next one continues after the delegation stored
+		/// under `last_key`.
+		Ongoing { pages: u32, last_key: Vec<u8> },
+		/// All `pages` pages were taken.
+		Complete { pages: u32 },
 	}
 
+	/// How far the snapshot of the election at the end of the current epoch got, if it started.
 	#[pallet::storage]
-	pub type Delegations<T: Config> = StorageMap<Key = T::AccountId, Value = DelegationInfo<T>>;
+	pub type SnapshotProgress<T> = StorageValue<Value = SnapshotStatus>;
 
-	#[derive(TypeInfo, Encode, Decode, MaxEncodedLen)]
+	/// The candidates of the election at the end of the current epoch, with their backing, frozen
+	/// when its snapshot starts.
+	#[pallet::storage]
+	pub type SnapshotTargets<T: Config> = StorageValue<Value = Vec<(T::AccountId, VoteWeight)>>;
+
+	/// The voters of the election at the end of the current epoch, by page. The first page starts
+	/// with the self vote of every candidate.
+	#[pallet::storage]
+	pub type SnapshotVoters<T: Config> = StorageMap<Key = u32, Value = Vec<Voter<T::AccountId>>>;
+
+	/// A verified solution to the election at the end of the current epoch.
+	#[derive(TypeInfo, Encode, Decode, CloneNoBound, PartialEqNoBound, RuntimeDebugNoBound)]
 	#[scale_info(skip_type_params(T))]
-	pub struct ValidatorBacking<T: Config> {
-		who: T::AccountId,
-		amount: BalanceOf<T>,
-	}
-
-	// This vector should always be sorted, with the lowest amount delegated at the end.
-	// When a delegator delegates to a validator, and we increase their backing, we check if that
-	// validator has more delegation than the last element in this vec.
-	//
-	// If so, we pop and swap.
-	//
-	// We must worry though that this validator loses delegations, and then stays in this list, but
-	// isn't the highest quality....
-	//
-	// BUT, we provide a simple extrinsic for **anyone** to claim that a validator should be in this
-	// list.
-	#[pallet::storage]
-	pub type TopValidators<T: Config> =
-		StorageValue<Value = BoundedVec<ValidatorBacking<T>, ConstU32<100>>>;
+	pub struct ReadySolution<T: Config> {
+		// The elected validators.
+		pub winners: BoundedVec<T::AccountId, T::MaxValidators>,
+		// How the stake of each voter is spread over the winners it backs.
+		pub distribution: Assignments<T::AccountId>,
+		// The score of the solution.
+		pub score: ElectionScore,
+	}
+
+	/// The best solution submitted for the election at the end of the current epoch so far.
+	#[pallet::storage]
+	pub type QueuedSolution<T: Config> = StorageValue<Value = ReadySolution<T>>;
 
 	/// Pallets use events to inform users when important changes are made.
 	/// https://paritytech.github.io/polkadot-sdk/master/polkadot_sdk_docs/guides/your_first_pallet/index.html#event-and-error
@@ -120,6 +692,68 @@ pub mod pallet {
 	pub enum Event<T: Config> {
 		/// We usually use passive tense for events.
 		SomethingStored { something: u32, who: T::AccountId },
+		/// A new validator candidate registered with the given self stake and commission.
+		ValidatorRegistered { who: T::AccountId, self_stake: BalanceOf<T>, commission: Perbill },
+		/// A validator candidate left the candidate pool and started unbonding its self stake.
+		ValidatorUnregistered { who: T::AccountId, self_stake: BalanceOf<T> },
+		/// A validator changed its commission.
+		CommissionUpdated { who: T::AccountId, commission: Perbill },
+		/// A delegator added `amount` to their delegation.
+		Delegated { delegator: T::AccountId, amount: BalanceOf<T> },
+		/// A delegator started unbonding `amount` of their delegation.
+		Undelegated { delegator: T::AccountId, amount: BalanceOf<T> },
+		/// The nomination of `validator` by `delegator` is not counted, as the validator has
+		/// [`Config::MaxDelegatorsPerValidator`] delegators with more stake.
+		Oversubscribed { delegator: T::AccountId, validator: T::AccountId },
+		/// `reporter` had the oversubscribed nomination of `validator` by `delegator` counted.
+		DelegatorCounted {
+			reporter: T::AccountId,
+			delegator: T::AccountId,
+			validator: T::AccountId,
+		},
+		/// `reaper` removed the delegation of `delegator`, which was below the minimum.
+		DelegationReaped { delegator: T::AccountId, reaper: T::AccountId },
+		/// A delegator nominated `targets` with their whole delegation.
+		Nominated { delegator: T::AccountId, targets: Vec<T::AccountId> },
+		/// `who` received `amount` from the rewards of the blocks authored by `validator`.
+		Rewarded { who: T::AccountId, validator: T::AccountId, amount: BalanceOf<T> },
+		/// The rewards of `who` now go to `dest`.
+		PayeeSet { who: T::AccountId, dest: RewardDestination<T::AccountId> },
+		/// The payout of `epoch` was set: `validator_payout` shared by the validators by the
+		/// blocks they authored, to be paid out with `payout_stakers`, and `remainder` handed to
+		/// [`Config::RewardRemainder`].
+		EpochPaid { epoch: EpochIndex, validator_payout: BalanceOf<T>, remainder: BalanceOf<T> },
+		/// `who` withdrew `amount` of unbonded stake, which is no longer held.
+		Withdrawn { who: T::AccountId, amount: BalanceOf<T> },
+		/// `who` lost `amount` of its bonded or unbonding stake to a slash.
+		Slashed { who: T::AccountId, amount: BalanceOf<T> },
+		/// `who` only authored `authored` of its `expected` slots in `epoch`, and is slashed by
+		/// `slash`.
+		Offence {
+			who: T::AccountId,
+			epoch: EpochIndex,
+			expected: u32,
+			authored: u32,
+			slash: Perbill,
+		},
+		/// `who` was taken out of elections, keeping its bond and delegations.
+		Chilled { who: T::AccountId },
+		/// `who` is back in elections after being chilled.
+		Validating { who: T::AccountId },
+		/// The slash of `fraction` of the stake behind `who` is deferred to the start of `epoch`.
+		SlashDeferred { who: T::AccountId, fraction: Perbill, epoch: EpochIndex },
+		/// The slash of `who` deferred to the start of `epoch` was cancelled.
+		SlashCancelled { who: T::AccountId, epoch: EpochIndex },
+		/// A new epoch started with a newly elected validator set.
+		NewEpoch { epoch: EpochIndex, validators: Vec<T::AccountId> },
+		/// A new epoch started, but there were no candidates, so the previous set was kept.
+		EmptyElection { epoch: EpochIndex },
+		/// `validators` were elected to become active at the start of `epoch`.
+		ValidatorsQueued { epoch: EpochIndex, validators: Vec<T::AccountId> },
+		/// `reporter` moved `who` in front of `lighter` within their bag of the [`CandidateList`].
+		CandidateMoved { reporter: T::AccountId, who: T::AccountId, lighter: T::AccountId },
+		/// A solution with `score` was queued for the election starting `epoch`.
+		SolutionStored { epoch: EpochIndex, score: ElectionScore },
 	}
 
 	/// Errors inform users that something went wrong.
@@ -127,21 +761,286 @@ pub mod pallet {
 	#[pallet::error]
 	pub enum Error<T> {
 		TooManyValidators,
+		/// The account is already a registered validator candidate.
+		AlreadyValidator,
+		/// The account is not a registered validator candidate.
+		NotValidator,
+		/// The bond is below the minimum required.
+		InsufficientBond,
+		/// The account is already delegating.
+		AlreadyDelegator,
+		/// The account is not delegating.
+		NotDelegator,
+		/// There are as many delegators as the snapshot of an election can hold.
+		TooManyDelegators,
+		/// The delegation amount is below the minimum required.
+		InsufficientDelegation,
+		/// Trying to withdraw more than what is delegated.
+		NotEnoughDelegated,
+		/// The commission would change by more than [`Config::MaxCommissionChange`] this epoch.
+		CommissionChangeTooLarge,
+		/// The account has too many unlocking chunks. Withdraw some first.
+		TooManyUnlockingChunks,
+		/// There is no unbonded stake to withdraw yet.
+		NothingToWithdraw,
+		/// The delegation chain would be longer than [`Config::MaxDelegationDepth`].
+		DelegationTooDeep,
+		/// The delegation chain would lead back to the delegator.
+		DelegationCycle,
+		/// Other delegators delegate through this delegator, so it cannot leave or move.
+		HasChainedDelegators,
+		/// A delegation has to nominate at least one target.
+		NoTargets,
+		/// More targets than [`Config::MaxNominations`] were nominated.
+		TooManyTargets,
+		/// The same validator was nominated more than once.
+		DuplicateTarget,
+		/// The candidates are not in the same bag of the [`CandidateList`].
+		NotInSameBag,
+		/// The candidate to move does not have more backing than the one to move it in front of.
+		NotHeavier,
+		/// The delegator does not back the validator with any stake.
+		NotNominated,
+		/// The nomination is already counted.
+		AlreadyCounted,
+		/// The validator has no room for the nomination, nor a counted one with less stake.
+		NotEnoughToCount,
+		/// There is no snapshot for the election the solution is for.
+		ElectionClosed,
+		/// The solution does not beat the score of the [`QueuedSolution`], or does worse than the
+		/// fallback election.
+		WeakSolution,
+		/// The solution is not a valid election of the snapshot.
+		InfeasibleSolution,
+		/// The solution does not have the score it claims.
+		WrongScore,
+		/// There is no slash of the validator deferred to the given epoch.
+		NoDeferredSlash,
+		/// The validator is already chilled.
+		AlreadyChilled,
+		/// The validator is not chilled.
+		NotChilled,
+		/// The delegation is not below [`Config::MinDelegation`].
+		NotDust,
+		/// The account is neither a validator nor a delegator.
+		NotStaker,
+		/// The validator has no payout left for the epoch, or the epoch is too far back.
+		NothingToPayOut,
 	}
 
 	#[pallet::hooks]
 	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
 		fn on_initialize(n: BlockNumberFor<T>) -> Weight {
+			let mut weight = Self::reward_author(n);
+			weight.saturating_accrue(Self::note_slot(n));
+
 			// This is a pretty lightweight check that we do EVERY block, but then tells us when an
 			// Epoch has passed...
-			if n % T::EpochDuration::get() == BlockNumberFor::<T>::zero() {
-				// CHANGE VALIDATORS LOGIC
-				// You cannot return an error here, so you have to be clever with your code...
+			let epoch_duration = T::EpochDuration::get();
+			let phase = T::UnsignedPhase::get();
+			// The snapshot is taken in the blocks right before the unsigned phase.
+			let snapshot_end = epoch_duration.saturating_sub(phase);
+			let snapshot_start = snapshot_end.saturating_sub(T::SnapshotPages::get().into());
+			let offset = n % epoch_duration;
+			if offset.is_zero() {
+				weight.saturating_accrue(Self::pay_epoch());
+				weight.saturating_accrue(Self::punish_missed_slots());
+				weight.saturating_accrue(Self::apply_deferred_slashes());
+				weight.saturating_accrue(Self::elect_validators());
+			} else if !phase.is_zero() && offset >= snapshot_start && offset < snapshot_end {
+				weight.saturating_accrue(Self::create_snapshot_page());
+			}
+
+			weight
+		}
+
+		fn offchain_worker(_: BlockNumberFor<T>) {
+			let Some(snapshot) = Self::snapshot() else { return };
+
+			// Mine once per election. Submitting the same solution again would be rejected anyway.
+			let last_mined = StorageValueRef::persistent(OFFCHAIN_LAST_MINED);
+			if last_mined.get::<EpochIndex>().ok().flatten() == Some(snapshot.epoch) {
+				return
+			}
+
+			let Some(solution) = Self::mine_solution(&snapshot) else { return };
+			if Self::unsigned_pre_checks(&solution).is_err() {
+				return
+			}
+			let call = Call::submit_solution { solution };
+			if SubmitTransaction::<T, Call<T>>::submit_unsigned_transaction(call.into()).is_ok() {
+				last_mined.set(&snapshot.epoch);
+			}
+		}
+
+		fn integrity_test() {
+			assert!(!T::EpochDuration::get().is_zero(), "epoch duration cannot be zero");
+			assert!(!T::MaxValidators::get().is_zero(), "max validators cannot be zero");
+			assert!(!T::MaxDelegationDepth::get().is_zero(), "max delegation depth cannot be zero");
+			assert!(!T::MaxNominations::get().is_zero(), "max nominations cannot be zero");
+			assert!(
+				T::HistoryDepth::get() > T::SlashDeferDuration::get(),
+				"history has to outlast deferred slashes"
+			);
+			if !T::UnsignedPhase::get().is_zero() {
+				assert!(!T::SnapshotPages::get().is_zero(), "snapshot pages cannot be zero");
+				assert!(!T::VotersPerPage::get().is_zero(), "voters per page cannot be zero");
+				assert!(
+					T::UnsignedPhase::get() + T::SnapshotPages::get().into() <
+						T::EpochDuration::get(),
+					"the snapshot and unsigned phase have to be shorter than an epoch"
+				);
+			}
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn try_state(_: BlockNumberFor<T>) -> Result<(), sp_runtime::TryRuntimeError> {
+			Self::do_try_state();
+			Ok(())
+		}
+	}
+
+	#[cfg(any(test, feature = "try-runtime"))]
+	impl<T: Config> Pallet<T> {
+		pub(crate) fn do_try_state() {
+			use fungible::InspectHold;
+			use sp_std::collections::btree_map::BTreeMap;
+
+			// What we expect to be held from each account as self stake, and as delegations.
+			let mut bonded: BTreeMap<T::AccountId, BalanceOf<T>> = BTreeMap::new();
+			let mut delegated_by: BTreeMap<T::AccountId, BalanceOf<T>> = BTreeMap::new();
+
+			let mut delegated: BTreeMap<T::AccountId, BalanceOf<T>> = BTreeMap::new();
+			assert_eq!(Delegations::<T>::count() as usize, Delegations::<T>::iter().count());
+			for (who, delegation) in Delegations::<T>::iter() {
+				// Only a delegation that others delegate through is kept around once slashed away.
+				assert!(!delegation.amount.is_zero() || Self::has_chained_delegators(&who));
+				assert!(!delegation.nominations.is_empty());
+				let mut total = BalanceOf::<T>::zero();
+				for nomination in delegation.nominations.iter() {
+					assert!(DelegatorsOf::<T>::contains_key(&nomination.validator, &who));
+					total.saturating_accrue(nomination.stake);
+					// The validator may be gone if it was slashed away entirely.
+					if Self::is_counted(&nomination.validator, &who) {
+						delegated
+							.entry(nomination.validator.clone())
+							.or_default()
+							.saturating_accrue(nomination.stake);
+					}
+				}
+				assert_eq!(total, delegation.amount);
+				if let [target] = delegation.targets.as_slice() {
+					if Delegations::<T>::contains_key(target) {
+						assert!(ChainedDelegators::<T>::contains_key(target, &who));
+					}
+				}
+				delegated_by.entry(who).or_default().saturating_accrue(delegation.amount);
+			}
+			for (validator, delegator, _) in DelegatorsOf::<T>::iter() {
+				let delegation = Delegations::<T>::get(&delegator).expect("delegator");
+				assert!(delegation.validators().contains(&validator));
+			}
+			for (validator, counted) in CountedDelegators::<T>::iter() {
+				assert!(!counted.is_empty());
+				for (delegator, stake) in counted {
+					assert!(!stake.is_zero(), "nominations slashed away are not counted");
+					let delegation = Delegations::<T>::get(&delegator).expect("counted delegator");
+					let nomination = delegation
+						.nominations
+						.iter()
+						.find(|n| n.validator == validator)
+						.expect("counted delegators nominate the validator");
+					assert_eq!(nomination.stake, stake);
+				}
+			}
+			for (delegate, delegator, _) in ChainedDelegators::<T>::iter() {
+				let delegation = Delegations::<T>::get(&delegator).expect("chained delegation");
+				assert_eq!(delegation.targets.to_vec(), vec![delegate.clone()]);
+				// The chain still ends at the same validators.
+				let delegate = Delegations::<T>::get(&delegate).expect("delegate is a delegator");
+				assert_eq!(delegation.validators(), delegate.validators());
+			}
+
+			for (who, info) in Validators::<T>::iter() {
+				assert!(!info.total_backing.is_zero());
+				assert_eq!(
+					info.total_backing,
+					info.self_stake.saturating_add(delegated.remove(&who).unwrap_or_default())
+				);
+				bonded.entry(who).or_default().saturating_accrue(info.self_stake);
+			}
+			// Every delegation points to a known validator record.
+			assert!(delegated.is_empty());
+
+			for (validator, who) in UnbondingFrom::<T>::iter_keys() {
+				assert!(Unlocking::<T>::get(&who).iter().any(|c| c.validator == validator));
+			}
+			for (who, chunks) in Unlocking::<T>::iter() {
+				assert!(!chunks.is_empty());
+				for chunk in chunks {
+					assert!(UnbondingFrom::<T>::contains_key(&chunk.validator, &who));
+					let expected =
+						if chunk.validator == who { &mut bonded } else { &mut delegated_by };
+					expected.entry(who.clone()).or_default().saturating_accrue(chunk.value);
+				}
+			}
+
+			for (who, amount) in bonded {
+				let reason = HoldReason::ValidatorBond.into();
+				assert_eq!(T::NativeBalance::balance_on_hold(&reason, &who), amount);
+			}
+			for (who, amount) in delegated_by {
+				let reason = HoldReason::Delegation.into();
+				assert_eq!(T::NativeBalance::balance_on_hold(&reason, &who), amount);
+			}
+
+			let listed = CandidateList::<T>::do_try_state();
+			let mut candidates = 0;
+			for (who, info) in Validators::<T>::iter().filter(|(_, info)| info.is_candidate()) {
+				if Chilled::<T>::contains_key(&who) {
+					assert!(
+						!CandidateList::<T>::contains(&who),
+						"chilled candidates are not listed"
+					);
+					continue
+				}
+				let node = CandidateNodes::<T>::get(&who).expect("candidates are listed");
+				assert_eq!(node.score, info.total_backing);
+				candidates += 1;
+			}
+			assert_eq!(listed, candidates, "only candidates are listed");
+
+			for (who, _) in Chilled::<T>::iter() {
+				let info = Validators::<T>::get(&who).expect("chilled validators have a record");
+				assert!(info.is_candidate(), "chilled validators are candidates");
+			}
+
+			for who in Payees::<T>::iter_keys() {
+				assert!(
+					Validators::<T>::contains_key(&who) || Delegations::<T>::contains_key(&who),
+					"only stakers have a payee"
+				);
 			}
 
-			// We return a default weight because we do not expect you to do weights for your
-			// project... Except for extra credit...
-			return Weight::default();
+			let current = CurrentEpoch::<T>::get();
+			for (epoch, _, exposure) in Exposures::<T>::iter() {
+				assert!(epoch <= current, "no exposure is taken ahead of its epoch");
+				assert!(
+					current.saturating_sub(epoch) <= T::HistoryDepth::get(),
+					"exposures are pruned after the history depth"
+				);
+				let others = exposure
+					.others
+					.iter()
+					.fold(Zero::zero(), |acc: BalanceOf<T>, (_, stake)| acc.saturating_add(*stake));
+				assert_eq!(exposure.total, exposure.own.saturating_add(others));
+			}
+			for (epoch, who) in EpochRewards::<T>::iter_keys() {
+				assert!(
+					Exposures::<T>::contains_key(epoch, &who),
+					"only exposed validators are paid"
+				);
+			}
 		}
 	}
 
@@ -186,29 +1085,2021 @@ pub mod pallet {
 			Ok(())
 		}
 
-		pub fn report_top_validator(origin: OriginFor<T>, _who: T::AccountId) -> DispatchResult {
-			let _who_cares = ensure_signed(origin)?;
-			//let validator_status = Validators::<T>::get(who);
+		/// Register the caller as a validator candidate, holding `self_stake` of their balance as
+		/// their self-bond, and taking `commission` of every block reward they earn.
+		pub fn register_validator(
+			origin: OriginFor<T>,
+			self_stake: BalanceOf<T>,
+			commission: Perbill,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(!Delegations::<T>::contains_key(&who), Error::<T>::AlreadyDelegator);
+			let mut info = Validators::<T>::get(&who).unwrap_or_default();
+			ensure!(!info.is_candidate(), Error::<T>::AlreadyValidator);
+			ensure!(
+				!self_stake.is_zero() && self_stake >= T::MinValidatorBond::get(),
+				Error::<T>::InsufficientBond
+			);
+			// If delegations are left over from a previous registration, re-registering must not
+			// be a way around the bound on commission changes.
+			if !info.total_backing.is_zero() {
+				Self::note_commission_change(&who, &info, commission)?;
+			}
+
+			T::NativeBalance::hold(&HoldReason::ValidatorBond.into(), &who, self_stake)?;
+			// A chill from before unregistering does not carry over.
+			Chilled::<T>::remove(&who);
+			// Delegations left over from a previous registration count towards the backing again.
+			info.self_stake = self_stake;
+			info.total_backing = info.total_backing.saturating_add(self_stake);
+			info.commission = commission;
+			Self::put_validator(&who, info);
+
+			Self::deposit_event(Event::ValidatorRegistered { who, self_stake, commission });
+			Ok(())
+		}
+
+		/// Change the commission of the calling validator.
+		///
+		/// Within one epoch, the commission can move at most [`Config::MaxCommissionChange`] away
+		/// from what it was at the start of that epoch.
+		pub fn set_commission(origin: OriginFor<T>, commission: Perbill) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			let mut info = Self::candidate(&who)?;
+			Self::note_commission_change(&who, &info, commission)?;
 
-			let _top_validators = TopValidators::<T>::get();
+			info.commission = commission;
+			Validators::<T>::insert(&who, info);
 
-			// we assume top_validators is stored
-			// let last_validator = top_validators.last();
-			// ensure!(last_validator.backing < validator_status.backing);
+			Self::deposit_event(Event::CommissionUpdated { who, commission });
+			Ok(())
+		}
 
-			// top_validators.insert(new_validator).sort().truncate_to_100()
+		/// Step out of elections, for instance for maintenance, keeping the self-bond and the
+		/// delegations of the caller.
+		///
+		/// If the caller is an active validator, it stays so until the end of the epoch.
+		pub fn chill(origin: OriginFor<T>) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			Self::candidate(&who)?;
+			ensure!(!Chilled::<T>::contains_key(&who), Error::<T>::AlreadyChilled);
 
-			// try to see if validator status is better than the last guy
+			Self::do_chill(&who);
 			Ok(())
 		}
-	}
 
-	impl<T: Config> Pallet<T> {
-		// A function to get you an account id for the current block author.
-		pub fn find_author() -> Option<T::AccountId> {
-			// If you want to see a realistic example of the `FindAuthor` interface, see
-			// `pallet-authorship`.
-			T::FindAuthor::find_author::<'_, Vec<_>>(Default::default())
+		/// Get back into elections after being chilled, either through `chill` or after an
+		/// offence.
+		pub fn validate(origin: OriginFor<T>) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			let info = Self::candidate(&who)?;
+			Chilled::<T>::take(&who).ok_or(Error::<T>::NotChilled)?;
+			Self::put_validator(&who, info);
+
+			Self::deposit_event(Event::Validating { who });
+			Ok(())
+		}
+
+		/// Remove the caller from the validator candidates, and start unbonding their self-bond.
+		///
+		/// Delegations towards the caller are left untouched, but they no longer back a candidate
+		/// until the delegators move them elsewhere or the caller registers again.
+		pub fn unregister_validator(origin: OriginFor<T>) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			let mut info = Self::candidate(&who)?;
+			let self_stake = sp_std::mem::take(&mut info.self_stake);
+
+			Self::unbond(&who, &who, self_stake)?;
+			info.total_backing = info.total_backing.saturating_sub(self_stake);
+			Chilled::<T>::remove(&who);
+			Self::put_validator(&who, info);
+
+			Self::deposit_event(Event::ValidatorUnregistered { who, self_stake });
+			Ok(())
+		}
+
+		/// Delegate `amount` of the caller's balance to `target`, either a validator candidate or
+		/// another delegator. Delegating to a delegator backs the validators at the end of its
+		/// chain.
+		///
+		/// Use `nominate` afterwards to back more than one validator. Fails once there are as many
+		/// delegators as fit into the snapshot of an election.
+		pub fn delegate(
+			origin: OriginFor<T>,
+			target: T::AccountId,
+			amount: BalanceOf<T>,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(!Delegations::<T>::contains_key(&who), Error::<T>::AlreadyDelegator);
+			ensure!(!Validators::<T>::contains_key(&who), Error::<T>::AlreadyValidator);
+			ensure!(
+				Delegations::<T>::count() < Self::max_delegators(),
+				Error::<T>::TooManyDelegators
+			);
+			ensure!(
+				!amount.is_zero() && amount >= T::MinDelegation::get(),
+				Error::<T>::InsufficientDelegation
+			);
+			let targets = BoundedVec::truncate_from(vec![target.clone()]);
+			let validators = Self::resolve_targets(&who, &targets)?;
+
+			T::NativeBalance::hold(
+				&HoldReason::DelegationDeposit.into(),
+				&who,
+				T::DelegationDeposit::get(),
+			)?;
+			T::NativeBalance::hold(&HoldReason::Delegation.into(), &who, amount)?;
+			let mut delegation = DelegationInfo {
+				targets: Default::default(),
+				amount,
+				nominations: Default::default(),
+			};
+			Self::nominate_validators(&who, &mut delegation, targets, validators);
+			Delegations::<T>::insert(&who, delegation);
+
+			Self::deposit_event(Event::Delegated { delegator: who.clone(), amount });
+			Self::deposit_event(Event::Nominated { delegator: who, targets: vec![target] });
+			Ok(())
+		}
+
+		/// Nominate `targets` with the caller's whole delegation, replacing its current targets.
+		///
+		/// The targets are either up to [`Config::MaxNominations`] validator candidates, or a
+		/// single other delegator. The delegation is split evenly over the validators backed.
+		pub fn nominate(origin: OriginFor<T>, targets: Vec<T::AccountId>) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			let mut delegation = Delegations::<T>::get(&who).ok_or(Error::<T>::NotDelegator)?;
+			ensure!(!Self::has_chained_delegators(&who), Error::<T>::HasChainedDelegators);
+			let targets: BoundedVec<_, _> =
+				targets.try_into().map_err(|_| Error::<T>::TooManyTargets)?;
+			let validators = Self::resolve_targets(&who, &targets)?;
+
+			Self::settle_rewards(&who, &mut delegation);
+			Self::clear_nominations(&who, &mut delegation);
+			Self::nominate_validators(&who, &mut delegation, targets.clone(), validators);
+			Delegations::<T>::insert(&who, delegation);
+
+			Self::deposit_event(Event::Nominated { delegator: who, targets: targets.into_inner() });
+			Ok(())
+		}
+
+		/// Top up the caller's existing delegation by `amount`, split evenly over its validators.
+		pub fn increase_delegation(origin: OriginFor<T>, amount: BalanceOf<T>) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			let mut delegation = Delegations::<T>::get(&who).ok_or(Error::<T>::NotDelegator)?;
+			ensure!(!amount.is_zero(), Error::<T>::InsufficientDelegation);
+			// Only candidates can receive more backing, use `nominate` otherwise.
+			for nomination in delegation.nominations.iter() {
+				Self::candidate(&nomination.validator)?;
+			}
+
+			T::NativeBalance::hold(&HoldReason::Delegation.into(), &who, amount)?;
+			Self::settle_rewards(&who, &mut delegation);
+			let stakes = Self::split_evenly(amount, delegation.nominations.len());
+			for (nomination, stake) in delegation.nominations.iter_mut().zip(stakes) {
+				let old = nomination.stake;
+				nomination.stake.saturating_accrue(stake);
+				Self::update_stake(&nomination.validator, &who, old, nomination.stake);
+			}
+			delegation.amount.saturating_accrue(amount);
+			Delegations::<T>::insert(&who, delegation);
+
+			Self::deposit_event(Event::Delegated { delegator: who, amount });
+			Ok(())
+		}
+
+		/// Start unbonding `amount` of the caller's delegation. Unbonding everything removes the
+		/// delegation, otherwise at least [`Config::MinDelegation`] has to be left.
+		pub fn decrease_delegation(origin: OriginFor<T>, amount: BalanceOf<T>) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			Self::do_undelegate(who, Some(amount))
+		}
+
+		/// Start unbonding the caller's whole delegation.
+		pub fn undelegate(origin: OriginFor<T>) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			Self::do_undelegate(who, None)
+		}
+
+		/// Release all of the caller's unbonding stake whose [`Config::UnbondingPeriod`] has
+		/// passed.
+		pub fn withdraw_unbonded(origin: OriginFor<T>) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			let current_epoch = CurrentEpoch::<T>::get();
+
+			let mut chunks = Unlocking::<T>::get(&who);
+			let mut self_stake = BalanceOf::<T>::zero();
+			let mut delegated = BalanceOf::<T>::zero();
+			let mut released = Vec::new();
+			chunks.retain(|chunk| {
+				if chunk.unlock_epoch > current_epoch {
+					return true
+				}
+				released.push(chunk.validator.clone());
+				if chunk.validator == who {
+					self_stake.saturating_accrue(chunk.value);
+				} else {
+					delegated.saturating_accrue(chunk.value);
+				}
+				false
+			});
+			let amount = self_stake.saturating_add(delegated);
+			ensure!(!amount.is_zero(), Error::<T>::NothingToWithdraw);
+
+			for (reason, value) in
+				[(HoldReason::ValidatorBond, self_stake), (HoldReason::Delegation, delegated)]
+			{
+				if !value.is_zero() {
+					T::NativeBalance::release(&reason.into(), &who, value, Precision::BestEffort)?;
+				}
+			}
+			for validator in released {
+				if !chunks.iter().any(|c| c.validator == validator) {
+					UnbondingFrom::<T>::remove(&validator, &who);
+				}
+			}
+			if chunks.is_empty() {
+				Unlocking::<T>::remove(&who);
+			} else {
+				Unlocking::<T>::insert(&who, chunks);
+			}
+
+			Self::deposit_event(Event::Withdrawn { who, amount });
+			Ok(())
+		}
+
+		/// Pay out the rewards accrued by the caller's delegation so far.
+		///
+		/// Rewards are also paid out automatically whenever the delegation changes.
+		pub fn claim_rewards(origin: OriginFor<T>) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(Delegations::<T>::contains_key(&who), Error::<T>::NotDelegator);
+			Self::settle_rewards_of(&who);
+			Ok(())
+		}
+
+		/// Pay out the share of the epoch payout that `validator` earned in `epoch`, to it and the
+		/// delegators in its [`Exposure`] of that epoch. Anyone can call this.
+		///
+		/// Payouts are kept for [`Config::HistoryDepth`] epochs, and forfeited afterwards.
+		pub fn payout_stakers(
+			origin: OriginFor<T>,
+			validator: T::AccountId,
+			epoch: EpochIndex,
+		) -> DispatchResult {
+			ensure_signed(origin)?;
+			let reward =
+				EpochRewards::<T>::take(epoch, &validator).ok_or(Error::<T>::NothingToPayOut)?;
+			let exposure =
+				Exposures::<T>::get(epoch, &validator).ok_or(Error::<T>::NothingToPayOut)?;
+
+			Self::pay_stakers(&validator, reward, &exposure);
+			Ok(())
+		}
+
+		/// Remove the delegation of `delegator` if it is below [`Config::MinDelegation`], for
+		/// instance after a slash. Anyone can call this.
+		///
+		/// What is left of the delegation starts unbonding as with `undelegate`, and the deposit
+		/// of the delegation is released.
+		pub fn reap_dust_delegation(
+			origin: OriginFor<T>,
+			delegator: T::AccountId,
+		) -> DispatchResult {
+			let reaper = ensure_signed(origin)?;
+			let delegation = Delegations::<T>::get(&delegator).ok_or(Error::<T>::NotDelegator)?;
+			ensure!(delegation.amount < T::MinDelegation::get(), Error::<T>::NotDust);
+
+			Self::do_undelegate(delegator.clone(), None)?;
+			Self::deposit_event(Event::DelegationReaped { delegator, reaper });
+			Ok(())
+		}
+
+		/// Choose where the rewards of the calling validator or delegator go from now on.
+		///
+		/// Rewards accrued by a delegation so far are paid out with the new destination.
+		pub fn set_payee(
+			origin: OriginFor<T>,
+			dest: RewardDestination<T::AccountId>,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(
+				Validators::<T>::contains_key(&who) || Delegations::<T>::contains_key(&who),
+				Error::<T>::NotStaker
+			);
+
+			if dest == RewardDestination::Free {
+				Payees::<T>::remove(&who);
+			} else {
+				Payees::<T>::insert(&who, dest.clone());
+			}
+			Self::deposit_event(Event::PayeeSet { who, dest });
+			Ok(())
+		}
+
+		/// Move the candidate `who` in front of `lighter`, if they are in the same bag of the
+		/// [`CandidateList`] but `who` has more backing.
+		///
+		/// Candidates within a bag are not sorted, so anyone can fix up the order within the bag
+		/// that the top of the list ends in. A valid move is free, an invalid one pays the full
+		/// fee.
+		pub fn put_in_front_of(
+			origin: OriginFor<T>,
+			who: T::AccountId,
+			lighter: T::AccountId,
+		) -> DispatchResultWithPostInfo {
+			let reporter = ensure_signed(origin)?;
+			CandidateList::<T>::put_in_front_of(&who, &lighter)?;
+
+			Self::deposit_event(Event::CandidateMoved { reporter, who, lighter });
+			Ok(Pays::No.into())
+		}
+
+		/// Count the oversubscribed nomination of `validator` by `delegator`, if `validator` has
+		/// room in [`CountedDelegators`] or a counted nomination with less stake, which is then
+		/// oversubscribed instead.
+		///
+		/// Counted nominations keep their spot however much they shrink, and spots that free up
+		/// are not filled on their own, so anyone can swap in the nominations that now belong.
+		/// A valid swap is free, an invalid one pays the full fee.
+		pub fn count_delegator(
+			origin: OriginFor<T>,
+			validator: T::AccountId,
+			delegator: T::AccountId,
+		) -> DispatchResultWithPostInfo {
+			let reporter = ensure_signed(origin)?;
+			let mut delegation =
+				Delegations::<T>::get(&delegator).ok_or(Error::<T>::NotDelegator)?;
+			let stake = delegation
+				.nominations
+				.iter()
+				.find(|nomination| nomination.validator == validator)
+				.map(|nomination| nomination.stake)
+				.filter(|stake| !stake.is_zero())
+				.ok_or(Error::<T>::NotNominated)?;
+			ensure!(!Self::is_counted(&validator, &delegator), Error::<T>::AlreadyCounted);
+			let has_room = CountedDelegators::<T>::get(&validator).len() <
+				T::MaxDelegatorsPerValidator::get() as usize;
+			ensure!(
+				has_room || Self::next_evicted(&validator, stake).is_some(),
+				Error::<T>::NotEnoughToCount
+			);
+
+			// The nomination is not counted, so settling only checkpoints it.
+			Self::settle_rewards(&delegator, &mut delegation);
+			Self::update_stake(&validator, &delegator, stake, stake);
+			Delegations::<T>::insert(&delegator, delegation);
+
+			Self::deposit_event(Event::DelegatorCounted { reporter, delegator, validator });
+			Ok(Pays::No.into())
+		}
+
+		/// Submit a solution to the election at the end of the current epoch. Meant to be
+		/// submitted by offchain workers only.
+		///
+		/// The solution is queued if it is a feasible election of the snapshot that fills every
+		/// seat, does no worse than the fallback election, and beats the score of the
+		/// [`QueuedSolution`], if any.
+		#[pallet::weight(Pallet::<T>::submit_solution_weight())]
+		pub fn submit_solution(origin: OriginFor<T>, solution: RawSolution) -> DispatchResult {
+			ensure_none(origin)?;
+			let snapshot = Self::unsigned_pre_checks(&solution)?;
+			let ready = Self::feasibility_check(&snapshot, &solution)?;
+
+			QueuedSolution::<T>::put(ready);
+			Self::deposit_event(Event::SolutionStored {
+				epoch: solution.epoch,
+				score: solution.score,
+			});
+			Ok(())
+		}
+
+		/// Cancel the slash of `validator` deferred to the start of `epoch`.
+		///
+		/// The validator stays chilled.
+		pub fn cancel_deferred_slash(
+			origin: OriginFor<T>,
+			epoch: EpochIndex,
+			validator: T::AccountId,
+		) -> DispatchResult {
+			T::SlashCancelOrigin::ensure_origin(origin)?;
+			UnappliedSlashes::<T>::try_mutate_exists(epoch, |slashes| {
+				let pending = slashes.as_mut().ok_or(Error::<T>::NoDeferredSlash)?;
+				let index = pending
+					.iter()
+					.position(|slash| slash.validator == validator)
+					.ok_or(Error::<T>::NoDeferredSlash)?;
+				pending.remove(index);
+				if pending.is_empty() {
+					*slashes = None;
+				}
+				Ok::<_, Error<T>>(())
+			})?;
+
+			Self::deposit_event(Event::SlashCancelled { who: validator, epoch });
+			Ok(())
+		}
+	}
+
+	#[pallet::validate_unsigned]
+	impl<T: Config> ValidateUnsigned for Pallet<T> {
+		type Call = Call<T>;
+
+		fn validate_unsigned(source: TransactionSource, call: &Self::Call) -> TransactionValidity {
+			let Call::submit_solution { solution } = call else {
+				return InvalidTransaction::Call.into()
+			};
+			// Only solutions of the local offchain worker are accepted, and they are not gossiped.
+			if !matches!(source, TransactionSource::Local | TransactionSource::InBlock) {
+				return InvalidTransaction::Call.into()
+			}
+
+			let snapshot =
+				Self::unsigned_pre_checks(solution).map_err(|_| InvalidTransaction::Stale)?;
+			Self::feasibility_check(&snapshot, solution)
+				.map_err(|_| InvalidTransaction::BadProof)?;
+
+			ValidTransaction::with_tag_prefix("DposOffchainElection")
+				// Better solutions replace worse ones in the pool.
+				.priority(
+					T::UnsignedPriority::get()
+						.saturating_add(solution.score.minimal_stake.saturated_into()),
+				)
+				.and_provides(solution.epoch)
+				.longevity(T::UnsignedPhase::get().saturated_into())
+				.propagate(false)
+				.build()
+		}
+	}
+
+	impl<T: Config> Pallet<T> {
+		/// The validator assigned to the slot of block `n`, if there are active validators.
+		///
+		/// Slots go round-robin through the [`ActiveValidators`], unless
+		/// [`Config::StakeWeightedSlots`] is set, in which case each slot goes to a validator
+		/// drawn from the [`SlotSeed`] of the epoch and `n`, with a chance proportional to its
+		/// [`ActiveBacking`]. Either way, the schedule is fixed for the whole epoch.
+		pub fn slot_author(n: BlockNumberFor<T>) -> Option<T::AccountId> {
+			let validators = ActiveValidators::<T>::get();
+			if validators.is_empty() {
+				return None;
+			}
+			let slot: u128 = n.saturated_into();
+			if !T::StakeWeightedSlots::get() {
+				let index = slot % validators.len() as u128;
+				return validators.get(index as usize).cloned();
+			}
+
+			let backing = ActiveBacking::<T>::get();
+			let total = backing
+				.iter()
+				.fold(0u128, |total, b| total.saturating_add((*b).saturated_into()));
+			if total.is_zero() {
+				return validators.first().cloned();
+			}
+			let random = T::Hashing::hash_of(&(SlotSeed::<T>::get(), slot));
+			let mut pick = u128::decode(&mut TrailingZeroInput::new(random.as_ref()))
+				.expect("decoding from trailing zeroes never fails; qed") %
+				total;
+			validators.into_iter().zip(backing).find_map(|(who, b)| {
+				let b: u128 = b.saturated_into();
+				if pick < b {
+					Some(who)
+				} else {
+					pick -= b;
+					None
+				}
+			})
+		}
+
+		// A function to get you an account id for the current block author.
+		pub fn find_author() -> Option<T::AccountId> {
+			// If you want to see a realistic example of the `FindAuthor` interface, see
+			// `pallet-authorship`.
+			let digest = frame_system::Pallet::<T>::digest();
+			T::FindAuthor::find_author(digest.logs.iter().filter_map(|item| item.as_pre_runtime()))
+		}
+
+		/// Reward the author of the current block with [`Config::BlockReward`], split pro-rata
+		/// between the author's self stake and its delegations. Authors other than the one the
+		/// slot of block `n` is assigned to are not rewarded.
+		///
+		/// The author's share is minted right away. The delegators' share is only added to the
+		/// author's `reward_per_share`, and minted once each delegator settles their rewards. This
+		/// keeps the cost of this function independent of the number of delegators. Returns the
+		/// weight consumed.
+		pub(crate) fn reward_author(n: BlockNumberFor<T>) -> Weight {
+			let Some(author) = Self::find_author() else { return Weight::zero() };
+			// Reading the slot schedule.
+			let weight = T::DbWeight::get().reads(3);
+			// Nothing vouches for the author found, so it has to be the one of the slot.
+			if Self::slot_author(n).as_ref() != Some(&author) {
+				return weight
+			}
+			weight.saturating_add(Self::reward_validator(&author, T::BlockReward::get()))
+		}
+
+		/// Split `reward` between `validator` and its delegators, as the reward of the blocks it
+		/// authored. Returns the weight consumed.
+		fn reward_validator(validator: &T::AccountId, reward: BalanceOf<T>) -> Weight {
+			let db = T::DbWeight::get();
+			let Some(mut info) = Validators::<T>::get(validator) else { return db.reads(1) };
+			if reward.is_zero() {
+				return db.reads(1)
+			}
+
+			let delegated = info.total_backing.saturating_sub(info.self_stake);
+			let validator_reward =
+				Self::validator_share(reward, info.commission, info.self_stake, info.total_backing);
+			if !delegated.is_zero() {
+				let delegators_reward = reward.saturating_sub(validator_reward);
+				info.reward_per_share = info.reward_per_share.saturating_add(
+					FixedU128::saturating_from_rational(delegators_reward, delegated),
+				);
+			}
+
+			// Only candidates can add to their self stake.
+			let restake = info.is_candidate().then_some(HoldReason::ValidatorBond);
+			if Self::mint_reward(validator, validator, validator_reward, restake) {
+				info.self_stake.saturating_accrue(validator_reward);
+				info.total_backing.saturating_accrue(validator_reward);
+				Self::put_validator(validator, info);
+				// Reading and writing the validator's record, writing its account and hold, and
+				// moving it to another bag, reading and writing both bags and neighbours.
+				return db.reads_writes(1 + 5, 3 + 6)
+			}
+			if !delegated.is_zero() {
+				Validators::<T>::insert(validator, info);
+			}
+
+			// Reading and writing the validator's record, and writing the validator's account.
+			db.reads_writes(1, 2)
+		}
+
+		/// The part of `reward` that goes to a validator with `commission` and `own` out of the
+		/// `total` stake behind it. The commission comes off the top, the rest is shared according
+		/// to stake.
+		fn validator_share(
+			reward: BalanceOf<T>,
+			commission: Perbill,
+			own: BalanceOf<T>,
+			total: BalanceOf<T>,
+		) -> BalanceOf<T> {
+			if own == total {
+				return reward
+			}
+			let commission = commission * reward;
+			let shared = reward.saturating_sub(commission);
+			commission.saturating_add(Perbill::from_rational(own, total) * shared)
+		}
+
+		/// Set the [`Config::EraPayout`] of the epoch that is ending.
+		///
+		/// The validators' payout is shared by the validators by the number of blocks they
+		/// authored in the epoch, and kept in [`EpochRewards`] until `payout_stakers` splits it
+		/// with the delegators of their [`Exposure`]. The remainder is handed to
+		/// [`Config::RewardRemainder`] right away. Returns the weight consumed.
+		pub(crate) fn pay_epoch() -> Weight {
+			let db = T::DbWeight::get();
+			let total_staked = ActiveBacking::<T>::get()
+				.into_iter()
+				.fold(BalanceOf::<T>::zero(), |total, backing| total.saturating_add(backing));
+			let total_issuance = T::NativeBalance::total_issuance();
+			let (validator_payout, remainder) =
+				T::EraPayout::era_payout(total_staked, total_issuance);
+			// Reading `CurrentEpoch`, `ActiveBacking` and the total issuance.
+			let mut weight = db.reads(3);
+			if validator_payout.is_zero() && remainder.is_zero() {
+				return weight
+			}
+
+			let epoch = CurrentEpoch::<T>::get();
+			// Only validators with an exposure can pay out their share.
+			let authored = SlotsOf::<T>::iter()
+				.filter(|(who, _)| Exposures::<T>::contains_key(epoch, who))
+				.map(|(who, record)| (who, record.authored))
+				.collect::<Vec<_>>();
+			// Reading every slot record and exposure, and writing the share of each validator.
+			weight.saturating_accrue(
+				db.reads_writes(2 * authored.len() as u64, authored.len() as u64),
+			);
+			let total_authored =
+				authored.iter().fold(0u32, |total, (_, n)| total.saturating_add(*n));
+			// Shares are rounded down, and nothing is paid if no block was authored at all.
+			let mut paid = BalanceOf::<T>::zero();
+			if !total_authored.is_zero() {
+				for (who, n) in authored {
+					let reward = Perbill::from_rational(n, total_authored) * validator_payout;
+					if !reward.is_zero() {
+						paid.saturating_accrue(reward);
+						EpochRewards::<T>::insert(epoch, who, reward);
+					}
+				}
+			}
+
+			if !remainder.is_zero() {
+				T::RewardRemainder::on_unbalanced(T::NativeBalance::issue(remainder));
+				// Writing the total issuance, and whatever the handler writes.
+				weight.saturating_accrue(db.writes(2));
+			}
+			Self::deposit_event(Event::EpochPaid { epoch, validator_payout: paid, remainder });
+			weight
+		}
+
+		/// Split the `reward` of `validator` between it and the delegators in its `exposure`, in
+		/// proportion to their stake, and pay each of them.
+		fn pay_stakers(validator: &T::AccountId, reward: BalanceOf<T>, exposure: &Exposure<T>) {
+			let validator_reward =
+				Self::validator_share(reward, exposure.commission, exposure.own, exposure.total);
+			let delegators_reward = reward.saturating_sub(validator_reward);
+			let delegated = exposure.total.saturating_sub(exposure.own);
+
+			// Only candidates can add to their self stake.
+			let candidate = Validators::<T>::get(validator).filter(|info| info.is_candidate());
+			if let Some(mut info) = candidate {
+				let restake = Some(HoldReason::ValidatorBond);
+				if Self::mint_reward(validator, validator, validator_reward, restake) {
+					info.self_stake.saturating_accrue(validator_reward);
+					info.total_backing.saturating_accrue(validator_reward);
+					Self::put_validator(validator, info);
+				}
+			} else {
+				Self::mint_reward(validator, validator, validator_reward, None);
+			}
+
+			for (delegator, stake) in exposure.others.iter() {
+				let amount = Perbill::from_rational(*stake, delegated) * delegators_reward;
+				Self::pay_delegator(delegator, validator, amount);
+			}
+		}
+
+		/// Pay `delegator` `amount` from the payout of `validator`, restaking it into its
+		/// nomination of `validator` if it still has one.
+		fn pay_delegator(delegator: &T::AccountId, validator: &T::AccountId, amount: BalanceOf<T>) {
+			let nominating = Delegations::<T>::get(delegator)
+				.filter(|d| d.nominations.iter().any(|n| &n.validator == validator));
+			// Only candidates can receive more backing, as with `increase_delegation`.
+			let Some(mut delegation) = nominating.filter(|_| Self::candidate(validator).is_ok())
+			else {
+				Self::mint_reward(delegator, validator, amount, None);
+				return
+			};
+
+			// The stake of the nomination may change, so its rewards are settled first.
+			Self::settle_rewards(delegator, &mut delegation);
+			if Self::mint_reward(delegator, validator, amount, Some(HoldReason::Delegation)) {
+				if let Some(nomination) =
+					delegation.nominations.iter_mut().find(|n| &n.validator == validator)
+				{
+					let old = nomination.stake;
+					nomination.stake.saturating_accrue(amount);
+					delegation.amount.saturating_accrue(amount);
+					Self::update_stake(validator, delegator, old, nomination.stake);
+				}
+			}
+			Delegations::<T>::insert(delegator, delegation);
+		}
+
+		/// Snapshot the [`Exposure`] of `validators` for `epoch`, and forget the epoch that falls
+		/// out of the [`Config::HistoryDepth`]. Returns the weight consumed.
+		fn snapshot_exposures(epoch: EpochIndex, validators: &[T::AccountId]) -> Weight {
+			let mut removed = 0u64;
+			if let Some(old) = epoch.checked_sub(T::HistoryDepth::get()) {
+				let limit = T::MaxValidators::get();
+				removed.saturating_accrue(
+					Exposures::<T>::clear_prefix(old, limit, None).unique.into(),
+				);
+				removed.saturating_accrue(
+					EpochRewards::<T>::clear_prefix(old, limit, None).unique.into(),
+				);
+			}
+
+			for who in validators {
+				let info = Validators::<T>::get(who).unwrap_or_default();
+				let exposure = Exposure {
+					own: info.self_stake,
+					total: info.total_backing,
+					commission: info.commission,
+					others: CountedDelegators::<T>::get(who),
+				};
+				Exposures::<T>::insert(epoch, who, exposure);
+			}
+
+			// Reading the record and the counted delegators of every validator, and writing its
+			// exposure.
+			let count = validators.len() as u64;
+			T::DbWeight::get().reads_writes(2 * count + removed, count + removed)
+		}
+
+		/// The rewards the delegation of `who` has accrued, but not yet been paid.
+		pub fn pending_rewards(who: &T::AccountId) -> BalanceOf<T> {
+			Delegations::<T>::get(who)
+				.map(|delegation| {
+					delegation
+						.nominations
+						.iter()
+						.filter(|nomination| Self::is_counted(&nomination.validator, who))
+						.fold(BalanceOf::<T>::zero(), |total, nomination| {
+							let reward_per_share = Self::reward_per_share(&nomination.validator);
+							total.saturating_add(Self::accrued_since(nomination, reward_per_share))
+						})
+				})
+				.unwrap_or_default()
+		}
+
+		/// Pay `who` the rewards accrued by each nomination of its `delegation`, and checkpoint
+		/// them at the current `reward_per_share` of their validators.
+		///
+		/// Must be called before the stake or the validators of a delegation change. Rewards
+		/// restaked per [`Payees`] are added to the nominations that earned them.
+		///
+		/// Nominations that are not counted accrue nothing, but are checkpointed all the same.
+		pub(crate) fn settle_rewards(who: &T::AccountId, delegation: &mut DelegationInfo<T>) {
+			for nomination in delegation.nominations.iter_mut() {
+				let reward_per_share = Self::reward_per_share(&nomination.validator);
+				if Self::is_counted(&nomination.validator, who) {
+					let reward = Self::accrued_since(nomination, reward_per_share);
+					// Only candidates can receive more backing, as with `increase_delegation`.
+					let restake = Self::candidate(&nomination.validator)
+						.is_ok()
+						.then_some(HoldReason::Delegation);
+					if Self::mint_reward(who, &nomination.validator, reward, restake) {
+						// The nomination is counted, so this cannot oversubscribe anyone.
+						let old = nomination.stake;
+						nomination.stake.saturating_accrue(reward);
+						delegation.amount.saturating_accrue(reward);
+						Self::update_stake(&nomination.validator, who, old, nomination.stake);
+					}
+				}
+				nomination.reward_per_share_paid = reward_per_share;
+			}
+		}
+
+		/// [`Self::settle_rewards`] of the delegation of `who`, if any.
+		pub(crate) fn settle_rewards_of(who: &T::AccountId) {
+			if let Some(mut delegation) = Delegations::<T>::get(who) {
+				Self::settle_rewards(who, &mut delegation);
+				Delegations::<T>::insert(who, delegation);
+			}
+		}
+
+		fn reward_per_share(validator: &T::AccountId) -> FixedU128 {
+			Validators::<T>::get(validator)
+				.map(|info| info.reward_per_share)
+				.unwrap_or_default()
+		}
+
+		/// The rewards `nomination` accrued between its checkpoint and `reward_per_share`.
+		pub(crate) fn accrued_since(
+			nomination: &Nomination<T>,
+			reward_per_share: FixedU128,
+		) -> BalanceOf<T> {
+			reward_per_share
+				.saturating_sub(nomination.reward_per_share_paid)
+				.saturating_mul_int(nomination.stake)
+		}
+
+		/// Mint `amount` as the reward of `who` for the blocks of `validator`, into the account
+		/// its [`Payees`] entry points to.
+		///
+		/// If `who` restakes its rewards and `restake` is given, they are minted into `who` and
+		/// held for `restake` instead. Adding them to the stake is up to the caller. Returns
+		/// whether they were held.
+		pub(crate) fn mint_reward(
+			who: &T::AccountId,
+			validator: &T::AccountId,
+			amount: BalanceOf<T>,
+			restake: Option<HoldReason>,
+		) -> bool {
+			if amount.is_zero() {
+				return false
+			}
+			let dest = Payees::<T>::get(who);
+			let payee = match &dest {
+				RewardDestination::Account(payee) => payee,
+				RewardDestination::Staked | RewardDestination::Free => who,
+			};
+			if T::NativeBalance::mint_into(payee, amount).is_err() {
+				return false
+			}
+			Self::deposit_event(Event::Rewarded {
+				who: who.clone(),
+				validator: validator.clone(),
+				amount,
+			});
+			match (dest, restake) {
+				(RewardDestination::Staked, Some(reason)) =>
+					T::NativeBalance::hold(&reason.into(), who, amount).is_ok(),
+				_ => false,
+			}
+		}
+
+		/// Start a new epoch with the validators of the [`QueuedSolution`], or if no solution was
+		/// submitted in time, the [`Config::MaxValidators`] with the most backing among the top
+		/// [`Config::MaxElectableCandidates`] of the [`CandidateList`].
+		///
+		/// The stake of delegators is spread over their validators the way the solution
+		/// distributes it before the new validators are exposed. The fallback leaves it as is.
+		///
+		/// With [`Config::ElectAhead`], the winners are queued instead, and the validators queued
+		/// by the previous election become active.
+		///
+		/// If there are no candidates at all, the previous set is kept rather than reporting an
+		/// empty set. Returns the weight consumed.
+		pub(crate) fn elect_validators() -> Weight {
+			let epoch = CurrentEpoch::<T>::mutate(|epoch| {
+				epoch.saturating_inc();
+				*epoch
+			});
+			let pages = Self::kill_snapshot();
+
+			// Reading and writing `CurrentEpoch`, taking `QueuedSolution` and the snapshot.
+			let mut writes = pages.saturating_add(4) as u64;
+			let (winners, distribution, reads) = match QueuedSolution::<T>::take() {
+				Some(mut solution) => {
+					// Candidates that left, were chilled, or dropped their registration since the
+					// snapshot are left out.
+					let reads = 3 + 3 * solution.winners.len() as u64;
+					solution.winners.retain(|who| {
+						Self::candidate(who).is_ok() &&
+							!Chilled::<T>::contains_key(who) &&
+							T::ValidatorRegistration::is_registered(who)
+					});
+					(solution.winners, solution.distribution, reads)
+				},
+				None => {
+					let mut top = Self::electable_candidates();
+					let reads = 3 + Self::list_reads(top.len());
+					// A stable sort, so candidates within a bag keep their order on ties.
+					top.sort_by_key(|(_, score)| Reverse(*score));
+					let top = top
+						.into_iter()
+						.take(T::MaxValidators::get() as usize)
+						.map(|(who, _)| who)
+						.collect::<Vec<_>>();
+					let top = top.try_into().expect("took at most `MaxValidators` items; qed");
+					(top, Vec::new(), reads)
+				},
+			};
+
+			let mut distributed = Weight::zero();
+			if winners.is_empty() {
+				Self::deposit_event(Event::EmptyElection { epoch });
+			} else {
+				distributed = Self::apply_distribution(distribution);
+			}
+
+			let (winners, reads) = if T::ElectAhead::get() {
+				// The session pallet activates the set it queued an epoch ago.
+				let activated = QueuedValidators::<T>::get();
+				if !winners.is_empty() {
+					QueuedValidators::<T>::put(&winners);
+					writes += 1;
+					Self::deposit_event(Event::ValidatorsQueued {
+						epoch: epoch.saturating_add(1),
+						validators: winners.to_vec(),
+					});
+				}
+				(activated, reads + 1)
+			} else {
+				(winners, reads)
+			};
+
+			if winners.is_empty() {
+				// The previous validators stay, with what is at stake behind them now.
+				let kept = ActiveValidators::<T>::get();
+				let weight = Self::snapshot_exposures(epoch, &kept);
+				return T::DbWeight::get()
+					.reads_writes(reads + 1, writes)
+					.saturating_add(distributed)
+					.saturating_add(weight);
+			}
+
+			let backing = winners
+				.iter()
+				.map(|who| Validators::<T>::get(who).map(|v| v.total_backing).unwrap_or_default())
+				.collect::<Vec<_>>();
+			let (seed, _) = T::Randomness::random(b"dpos/slots");
+
+			T::ReportNewValidatorSet::report_new_validator_set(winners.to_vec());
+			ActiveValidators::<T>::put(&winners);
+			ActiveBacking::<T>::put(BoundedVec::truncate_from(backing));
+			SlotSeed::<T>::put(seed);
+			Self::deposit_event(Event::NewEpoch { epoch, validators: winners.to_vec() });
+
+			// Also reading the backing of the winners and the randomness, and writing
+			// `ActiveValidators`, `ActiveBacking` and `SlotSeed`. `ReportNewValidatorSet` is
+			// expected to account for itself.
+			let reads = reads + winners.len() as u64 + 1;
+			writes += 3;
+			T::DbWeight::get()
+				.reads_writes(reads, writes)
+				.saturating_add(distributed)
+				.saturating_add(Self::snapshot_exposures(epoch, &winners))
+		}
+
+		/// Spread the counted stake of every voter in `distribution` over its counted nominations
+		/// in proportion to the stake the election assigned to each of them. Counted nominations
+		/// it assigned nothing are left without stake, oversubscribed ones are left as they are.
+		/// Returns the weight consumed.
+		///
+		/// Delegators that no longer have a counted nomination of any of the validators they were
+		/// assigned to are left as they are, and so are the self votes of candidates.
+		fn apply_distribution(distribution: Assignments<T::AccountId>) -> Weight {
+			let (mut reads, mut writes) = (0u64, 0u64);
+			for (who, assigned) in distribution {
+				reads += 1;
+				let Some(mut delegation) = Delegations::<T>::get(&who) else { continue };
+				let counted = delegation
+					.nominations
+					.iter()
+					.map(|nomination| Self::is_counted(&nomination.validator, &who))
+					.collect::<Vec<_>>();
+				let weights = delegation
+					.nominations
+					.iter()
+					.zip(counted.iter())
+					.map(|(nomination, counted)| {
+						assigned
+							.iter()
+							.find(|(validator, _)| *counted && *validator == nomination.validator)
+							.map_or(0, |(_, stake)| *stake)
+					})
+					.collect::<Vec<_>>();
+				if weights.iter().all(Zero::is_zero) {
+					continue
+				}
+				let counted_stake = |delegation: &DelegationInfo<T>| {
+					delegation
+						.nominations
+						.iter()
+						.zip(counted.iter())
+						.filter(|(_, counted)| **counted)
+						.fold(BalanceOf::<T>::zero(), |total, (n, _)| total.saturating_add(n.stake))
+				};
+				let unchanged = Self::split_in_proportion(counted_stake(&delegation), &weights)
+					.into_iter()
+					.zip(delegation.nominations.iter().zip(counted.iter()))
+					.all(|(stake, (nomination, counted))| !counted || stake == nomination.stake);
+				if unchanged {
+					continue
+				}
+
+				// Restaked rewards change the counted stake, so it is split once settled.
+				Self::settle_rewards(&who, &mut delegation);
+				let stakes = Self::split_in_proportion(counted_stake(&delegation), &weights);
+				let nominations = delegation.nominations.iter_mut().zip(counted.iter());
+				for ((nomination, _), stake) in nominations.filter(|(_, c)| **c).zip(stakes) {
+					if nomination.stake != stake {
+						Self::update_stake(&nomination.validator, &who, nomination.stake, stake);
+						nomination.stake = stake;
+					}
+				}
+				Delegations::<T>::insert(&who, delegation);
+
+				// Settling and updating the record, the counted delegators and the list node of
+				// every validator.
+				let count = weights.len() as u64;
+				reads += 4 * count;
+				writes += 1 + 3 * count;
+			}
+			T::DbWeight::get().reads_writes(reads, writes)
+		}
+
+		/// Take the next page of the snapshot of the election at the end of the current epoch.
+		/// Returns the weight consumed.
+		///
+		/// The first page freezes the top [`Config::MaxElectableCandidates`] of the
+		/// [`CandidateList`] as the candidates, and takes their self votes.
+		/// Every page then visits the next [`Config::VotersPerPage`] delegations, so that each
+		/// delegator is in the snapshot once, with the stake of its counted nominations as of the
+		/// page it is in.
+		pub(crate) fn create_snapshot_page() -> Weight {
+			// Reading `SnapshotProgress` and the total issuance.
+			let mut reads = 2;
+			let mut writes = 2;
+			let (page, last_key) = match SnapshotProgress::<T>::get() {
+				None => (0, None),
+				Some(SnapshotStatus::Ongoing { pages, last_key }) => (pages, Some(last_key)),
+				Some(SnapshotStatus::Complete { .. }) => return T::DbWeight::get().reads(1),
+			};
+
+			let factor = Self::vote_weight_factor();
+			let to_vote = |stake: BalanceOf<T>| -> VoteWeight { (stake / factor).saturated_into() };
+
+			let mut voters = Vec::new();
+			let candidates = match last_key {
+				None => {
+					let candidates = Self::electable_candidates()
+						.into_iter()
+						.map(|(who, backing)| (who, to_vote(backing)))
+						.collect::<Vec<_>>();
+					// Writing `SnapshotTargets`.
+					reads += Self::list_reads(candidates.len());
+					writes += 1;
+					for (candidate, _) in candidates.iter() {
+						reads += 1;
+						if let Some(info) = Validators::<T>::get(candidate) {
+							let vote = vec![candidate.clone()];
+							voters.push((candidate.clone(), to_vote(info.self_stake), vote));
+						}
+					}
+					SnapshotTargets::<T>::put(&candidates);
+					candidates
+				},
+				Some(_) => {
+					reads += 1;
+					SnapshotTargets::<T>::get().unwrap_or_default()
+				},
+			};
+			let candidates = candidates.into_iter().map(|(who, _)| who).collect::<BTreeSet<_>>();
+
+			let mut delegations = match last_key {
+				None => Delegations::<T>::iter(),
+				Some(last_key) => Delegations::<T>::iter_from(last_key),
+			};
+			let mut visited = 0;
+			for (delegator, delegation) in
+				delegations.by_ref().take(T::VotersPerPage::get() as usize)
+			{
+				visited += 1;
+				// Only counted nominations back their validators, so only they vote.
+				let mut stake = BalanceOf::<T>::zero();
+				let mut targets = Vec::new();
+				for nomination in delegation.nominations {
+					if candidates.contains(&nomination.validator) {
+						reads += 1;
+						if Self::is_counted(&nomination.validator, &delegator) {
+							stake.saturating_accrue(nomination.stake);
+							targets.push(nomination.validator);
+						}
+					}
+				}
+				if !targets.is_empty() {
+					voters.push((delegator, to_vote(stake), targets));
+				}
+			}
+			reads += visited;
+
+			let pages = page.saturating_add(1);
+			SnapshotVoters::<T>::insert(page, voters);
+			let status =
+				if visited < T::VotersPerPage::get() as u64 || pages >= T::SnapshotPages::get() {
+					SnapshotStatus::Complete { pages }
+				} else {
+					SnapshotStatus::Ongoing { pages, last_key: delegations.last_raw_key().to_vec() }
+				};
+			SnapshotProgress::<T>::put(status);
+
+			T::DbWeight::get().reads_writes(reads, writes)
+		}
+
+		/// The maximum number of delegators: as many as the pages of a snapshot can visit.
+		pub fn max_delegators() -> u32 {
+			T::SnapshotPages::get().saturating_mul(T::VotersPerPage::get())
+		}
+
+		/// The complete snapshot of the election at the end of the current epoch, if there is one.
+		pub fn snapshot() -> Option<ElectionSnapshot<T::AccountId>> {
+			let Some(SnapshotStatus::Complete { pages }) = SnapshotProgress::<T>::get() else {
+				return None
+			};
+			let (candidates, backing) = SnapshotTargets::<T>::get()?.into_iter().unzip();
+			let voters = (0..pages).flat_map(SnapshotVoters::<T>::get).flatten().collect();
+			let epoch = CurrentEpoch::<T>::get().saturating_add(1);
+			Some(ElectionSnapshot { epoch, candidates, backing, voters })
+		}
+
+		/// Remove the snapshot, returning the number of its pages.
+		fn kill_snapshot() -> u32 {
+			let pages = match SnapshotProgress::<T>::take() {
+				Some(
+					SnapshotStatus::Ongoing { pages, .. } | SnapshotStatus::Complete { pages },
+				) => pages,
+				None => 0,
+			};
+			SnapshotTargets::<T>::kill();
+			(0..pages).for_each(SnapshotVoters::<T>::remove);
+			pages
+		}
+
+		/// Elect the validators out of `snapshot` with [`Config::ElectionStrategy`], and turn the
+		/// result into a solution that can be submitted, unless it does worse than the fallback.
+		pub fn mine_solution(snapshot: &ElectionSnapshot<T::AccountId>) -> Option<RawSolution> {
+			let solution = Self::solve::<T::ElectionStrategy>(snapshot)?;
+			(!Self::fallback_score(snapshot)
+				.strict_threshold_better(solution.score, Perbill::zero()))
+			.then_some(solution)
+		}
+
+		/// The score of the fallback election as of `snapshot`: the candidates with the most
+		/// backing, each with the backing it had when the snapshot started. Solutions must do at
+		/// least as well.
+		fn fallback_score(snapshot: &ElectionSnapshot<T::AccountId>) -> ElectionScore {
+			if snapshot.backing.is_empty() {
+				return ElectionScore::default()
+			}
+			let mut backing = snapshot.backing.clone();
+			backing.sort_by_key(|total| Reverse(*total));
+			backing
+				.into_iter()
+				.take(T::MaxValidators::get() as usize)
+				.map(|total| (0u32, Support { total: total.into(), voters: Vec::new() }))
+				.collect::<Supports<u32>>()
+				.evaluate()
+		}
+
+		/// Elect the validators out of `snapshot` with `S`, and turn the result into a solution.
+		///
+		/// Strategies that back every candidate of a voter with its whole stake, like
+		/// [`crate::election::TopBacking`], have the stake of such voters scaled down to fit.
+		fn solve<S: ElectionStrategy<T::AccountId>>(
+			snapshot: &ElectionSnapshot<T::AccountId>,
+		) -> Option<RawSolution> {
+			let supports = S::elect(
+				T::MaxValidators::get() as usize,
+				snapshot.candidates.clone(),
+				snapshot.voters.clone(),
+			)
+			.ok()?;
+
+			let candidates: BTreeMap<_, _> =
+				snapshot.candidates.iter().enumerate().map(|(i, who)| (who, i as u32)).collect();
+			let voters: BTreeMap<_, _> = snapshot
+				.voters
+				.iter()
+				.enumerate()
+				.map(|(i, (who, _, _))| (who, i as u32))
+				.collect();
+
+			let mut winners = Vec::new();
+			let mut assignments = BTreeMap::<u32, Vec<(u32, VoteWeight)>>::new();
+			for (winner, support) in supports.into_iter().take(T::MaxValidators::get() as usize) {
+				let target = *candidates.get(&winner)?;
+				winners.push(target);
+				for (voter, stake) in support.voters {
+					let voter = *voters.get(&voter)?;
+					assignments.entry(voter).or_default().push((target, stake.saturated_into()));
+				}
+			}
+
+			let assignments = assignments
+				.into_iter()
+				.map(|(voter, mut distribution)| {
+					let budget = snapshot.voters[voter as usize].1;
+					let total = distribution.iter().fold(0u64, |t, (_, s)| t.saturating_add(*s));
+					if total > budget {
+						for (_, stake) in distribution.iter_mut() {
+							*stake = multiply_by_rational_with_rounding(
+								*stake as u128,
+								budget as u128,
+								total as u128,
+								Rounding::Down,
+							)
+							.unwrap_or_default()
+							.saturated_into();
+						}
+					}
+					(voter, distribution)
+				})
+				.collect::<Vec<_>>();
+
+			let score = Self::supports_of(snapshot, &winners, &assignments).ok()?.evaluate();
+			Some(RawSolution { epoch: snapshot.epoch, winners, assignments, score })
+		}
+
+		/// The checks of a submitted `solution` that do not need to go through it: that it is for
+		/// the current snapshot, which is returned, and beats the [`QueuedSolution`].
+		fn unsigned_pre_checks(
+			solution: &RawSolution,
+		) -> Result<ElectionSnapshot<T::AccountId>, Error<T>> {
+			let snapshot = Self::snapshot()
+				.filter(|snapshot| snapshot.epoch == solution.epoch)
+				.ok_or(Error::<T>::ElectionClosed)?;
+			ensure!(
+				QueuedSolution::<T>::get().is_none_or(|queued| solution
+					.score
+					.strict_threshold_better(queued.score, Perbill::zero())),
+				Error::<T>::WeakSolution
+			);
+			Ok(snapshot)
+		}
+
+		/// Check that `solution` is a valid election of `snapshot` with the score it claims, which
+		/// does no worse than the fallback, and return it as a [`ReadySolution`].
+		///
+		/// This is linear in the size of the solution, which is bounded by the size of the
+		/// snapshot and [`Config::MaxNominations`].
+		fn feasibility_check(
+			snapshot: &ElectionSnapshot<T::AccountId>,
+			solution: &RawSolution,
+		) -> Result<ReadySolution<T>, Error<T>> {
+			let supports = Self::supports_of(snapshot, &solution.winners, &solution.assignments)?;
+			ensure!(supports.evaluate() == solution.score, Error::<T>::WrongScore);
+			ensure!(
+				!Self::fallback_score(snapshot)
+					.strict_threshold_better(solution.score, Perbill::zero()),
+				Error::<T>::WeakSolution
+			);
+
+			let winners = solution
+				.winners
+				.iter()
+				.map(|&i| snapshot.candidates[i as usize].clone())
+				.collect::<Vec<_>>();
+			let winners = winners.try_into().map_err(|_| Error::<T>::InfeasibleSolution)?;
+			// `supports_of` checked every index.
+			let distribution = solution
+				.assignments
+				.iter()
+				.map(|(voter, distribution)| {
+					let voter = snapshot.voters[*voter as usize].0.clone();
+					let distribution = distribution
+						.iter()
+						.map(|(target, stake)| {
+							(snapshot.candidates[*target as usize].clone(), *stake)
+						})
+						.collect();
+					(voter, distribution)
+				})
+				.collect();
+			Ok(ReadySolution { winners, distribution, score: solution.score })
+		}
+
+		/// The supports that `assignments` of the stake of the voters in `snapshot` to `winners`
+		/// add up to, if they are valid.
+		///
+		/// There must be as many winners as seats, or candidates if there are fewer. Every voter
+		/// can only be assigned once, in increasing order, to winners it backs, and with no more
+		/// than its stake in total.
+		fn supports_of(
+			snapshot: &ElectionSnapshot<T::AccountId>,
+			winners: &[u32],
+			assignments: &[(u32, Vec<(u32, VoteWeight)>)],
+		) -> Result<Supports<u32>, Error<T>> {
+			let seats = snapshot.candidates.len().min(T::MaxValidators::get() as usize);
+			ensure!(!winners.is_empty() && winners.len() == seats, Error::<T>::InfeasibleSolution);
+			let mut position = BTreeMap::new();
+			for (i, &winner) in winners.iter().enumerate() {
+				ensure!(
+					(winner as usize) < snapshot.candidates.len() &&
+						position.insert(winner, i).is_none(),
+					Error::<T>::InfeasibleSolution
+				);
+			}
+
+			let mut supports: Supports<u32> =
+				winners.iter().map(|&winner| (winner, Support::default())).collect();
+			let mut previous = None;
+			for (voter, distribution) in assignments {
+				ensure!(previous < Some(voter), Error::<T>::InfeasibleSolution);
+				previous = Some(voter);
+				let (_, budget, targets) =
+					snapshot.voters.get(*voter as usize).ok_or(Error::<T>::InfeasibleSolution)?;
+
+				let mut total: VoteWeight = 0;
+				for (target, stake) in distribution {
+					let &i = position.get(target).ok_or(Error::<T>::InfeasibleSolution)?;
+					ensure!(
+						targets.contains(&snapshot.candidates[*target as usize]),
+						Error::<T>::InfeasibleSolution
+					);
+					total = total.checked_add(*stake).ok_or(Error::<T>::InfeasibleSolution)?;
+					let support = &mut supports[i].1;
+					support.total = support.total.saturating_add(*stake as u128);
+					support.voters.push((*voter, *stake as u128));
+				}
+				ensure!(total <= *budget, Error::<T>::InfeasibleSolution);
+			}
+			Ok(supports)
+		}
+
+		/// The worst-case weight of [`Pallet::submit_solution`]: reading a full snapshot, and
+		/// checking an assignment of each of its voters to up to [`Config::MaxNominations`]
+		/// winners, once for the solution and once for the fallback.
+		pub fn submit_solution_weight() -> Weight {
+			let pages = T::SnapshotPages::get();
+			let voters = T::MaxElectableCandidates::get()
+				.saturating_add(pages.saturating_mul(T::VotersPerPage::get()));
+			let edges = voters.saturating_mul(T::MaxNominations::get()).saturating_mul(2);
+			T::DbWeight::get()
+				.reads_writes(pages.saturating_add(4).into(), 1)
+				.saturating_add(SOLUTION_EDGE_WEIGHT.saturating_mul(edges.into()))
+		}
+
+		/// The factor by which stake is scaled down to fit into a [`VoteWeight`] for elections.
+		fn vote_weight_factor() -> BalanceOf<T> {
+			let max = BalanceOf::<T>::saturated_from(VoteWeight::MAX);
+			(<T::NativeBalance as fungible::Inspect<_>>::total_issuance() / max).max(One::one())
+		}
+
+		/// Check that `who` can move its commission from what `info` says to `new`, and record the
+		/// baseline for the current epoch if this is the first change in it.
+		fn note_commission_change(
+			who: &T::AccountId,
+			info: &ValidatorInfo<T>,
+			new: Perbill,
+		) -> DispatchResult {
+			let epoch = CurrentEpoch::<T>::get();
+			let baseline = match CommissionBaseline::<T>::get(who) {
+				Some((changed_in, baseline)) if changed_in == epoch => baseline,
+				_ => info.commission,
+			};
+			let change = if new > baseline { new - baseline } else { baseline - new };
+			ensure!(change <= T::MaxCommissionChange::get(), Error::<T>::CommissionChangeTooLarge);
+
+			CommissionBaseline::<T>::insert(who, (epoch, baseline));
+			Ok(())
+		}
+
+		/// Get the record of `who`, if it is a validator candidate.
+		pub fn candidate(who: &T::AccountId) -> Result<ValidatorInfo<T>, Error<T>> {
+			Validators::<T>::get(who)
+				.filter(|info| info.is_candidate())
+				.ok_or(Error::<T>::NotValidator)
+		}
+
+		/// The status of the candidate `who`, if it is one.
+		pub fn status(who: &T::AccountId) -> Option<ValidatorStatus> {
+			Self::candidate(who).ok()?;
+			Some(if Chilled::<T>::contains_key(who) {
+				ValidatorStatus::Chilled
+			} else {
+				ValidatorStatus::Validating
+			})
+		}
+
+		/// Unbond `amount` from the delegation of `who`, or all of it if `None`.
+		fn do_undelegate(who: T::AccountId, amount: Option<BalanceOf<T>>) -> DispatchResult {
+			let mut delegation = Delegations::<T>::get(&who).ok_or(Error::<T>::NotDelegator)?;
+			let amount = amount.unwrap_or(delegation.amount);
+			ensure!(amount <= delegation.amount, Error::<T>::NotEnoughDelegated);
+			let leaving = amount == delegation.amount;
+			// A delegation slashed down to nothing can still be removed.
+			ensure!(!amount.is_zero() || leaving, Error::<T>::InsufficientDelegation);
+			ensure!(
+				leaving || delegation.amount.saturating_sub(amount) >= T::MinDelegation::get(),
+				Error::<T>::InsufficientDelegation
+			);
+			ensure!(
+				!leaving || !Self::has_chained_delegators(&who),
+				Error::<T>::HasChainedDelegators
+			);
+
+			Self::settle_rewards(&who, &mut delegation);
+			let taken = Self::take_proportionally(&mut delegation.nominations, amount);
+			for (nomination, value) in delegation.nominations.iter().zip(taken) {
+				if !value.is_zero() {
+					Self::unbond(&who, &nomination.validator, value)?;
+					let old = nomination.stake.saturating_add(value);
+					Self::update_stake(&nomination.validator, &who, old, nomination.stake);
+				}
+			}
+			delegation.amount.saturating_reduce(amount);
+			if leaving {
+				Self::remove_delegation(&who, &delegation);
+			} else {
+				Delegations::<T>::insert(&who, delegation);
+			}
+
+			Self::deposit_event(Event::Undelegated { delegator: who, amount });
+			Ok(())
+		}
+
+		/// Find the validators that a delegation from `delegator` to `targets` would back, along
+		/// with their current `reward_per_share`.
+		///
+		/// Only a single target can be another delegator, whose validators are backed instead.
+		/// All validators have to be candidates.
+		fn resolve_targets(
+			delegator: &T::AccountId,
+			targets: &[T::AccountId],
+		) -> Result<Vec<(T::AccountId, FixedU128)>, DispatchError> {
+			ensure!(!targets.is_empty(), Error::<T>::NoTargets);
+			let validators = match targets {
+				[target] if Delegations::<T>::contains_key(target) =>
+					Self::resolve_chain(delegator, target)?,
+				_ => targets.to_vec(),
+			};
+
+			let mut unique = validators.clone();
+			unique.sort();
+			unique.dedup();
+			ensure!(unique.len() == validators.len(), Error::<T>::DuplicateTarget);
+
+			validators
+				.into_iter()
+				.map(|validator| {
+					let info = Self::candidate(&validator)?;
+					Ok((validator, info.reward_per_share))
+				})
+				.collect()
+		}
+
+		/// Find the validators that a delegation from `delegator` to the delegator `target` ends
+		/// up backing, by following the delegations from `target` onwards.
+		///
+		/// Fails if the chain is longer than [`Config::MaxDelegationDepth`], or if it leads back
+		/// to `delegator`.
+		fn resolve_chain(
+			delegator: &T::AccountId,
+			target: &T::AccountId,
+		) -> Result<Vec<T::AccountId>, DispatchError> {
+			let mut next = target.clone();
+			// Delegating to `target` is already the first step of the chain.
+			for _ in 1..T::MaxDelegationDepth::get() {
+				ensure!(&next != delegator, Error::<T>::DelegationCycle);
+				let delegation = Delegations::<T>::get(&next).ok_or(Error::<T>::NotDelegator)?;
+				match delegation.targets.as_slice() {
+					[followed] if Delegations::<T>::contains_key(followed) =>
+						next = followed.clone(),
+					// Everyone along a chain backs the same validators.
+					_ => return Ok(delegation.validators()),
+				}
+			}
+			Err(Error::<T>::DelegationTooDeep.into())
+		}
+
+		/// Point `delegation` of `who` at `targets`, and split its amount evenly over their
+		/// `validators`, as returned by [`Self::resolve_targets`].
+		///
+		/// The delegation must not nominate anyone at this point.
+		fn nominate_validators(
+			who: &T::AccountId,
+			delegation: &mut DelegationInfo<T>,
+			targets: BoundedVec<T::AccountId, T::MaxNominations>,
+			validators: Vec<(T::AccountId, FixedU128)>,
+		) {
+			if let [target] = targets.as_slice() {
+				if Delegations::<T>::contains_key(target) {
+					ChainedDelegators::<T>::insert(target, who, ());
+				}
+			}
+			let stakes = Self::split_evenly(delegation.amount, validators.len());
+			let nominations = validators
+				.into_iter()
+				.zip(stakes)
+				.map(|((validator, reward_per_share_paid), stake)| {
+					DelegatorsOf::<T>::insert(&validator, who, ());
+					Self::update_stake(&validator, who, Zero::zero(), stake);
+					Nomination { validator, stake, reward_per_share_paid }
+				})
+				.collect::<Vec<_>>();
+			delegation.targets = targets;
+			delegation.nominations = BoundedVec::truncate_from(nominations);
+		}
+
+		/// Withdraw all the stake of `delegation` of `who` from its validators, leaving it without
+		/// targets. Rewards must have been settled before.
+		fn clear_nominations(who: &T::AccountId, delegation: &mut DelegationInfo<T>) {
+			for nomination in delegation.nominations.iter() {
+				Self::update_stake(&nomination.validator, who, nomination.stake, Zero::zero());
+			}
+			Self::remove_reverse_index(who, delegation);
+			delegation.targets.clear();
+			delegation.nominations.clear();
+		}
+
+		/// Split `amount` evenly into `parts`, the first parts taking the remainder.
+		fn split_evenly(amount: BalanceOf<T>, parts: usize) -> Vec<BalanceOf<T>> {
+			let count = BalanceOf::<T>::saturated_from(parts.max(1));
+			let (share, remainder) = (amount / count, amount % count);
+			let remainder: usize = remainder.saturated_into();
+			(0..parts)
+				.map(|i| if i < remainder { share.saturating_add(One::one()) } else { share })
+				.collect()
+		}
+
+		/// Split `amount` in proportion to `weights`, the first part with any weight taking the
+		/// remainder.
+		fn split_in_proportion(amount: BalanceOf<T>, weights: &[VoteWeight]) -> Vec<BalanceOf<T>> {
+			let total = weights.iter().fold(0u128, |total, w| total.saturating_add(*w as u128));
+			let mut parts = weights
+				.iter()
+				.map(|weight| {
+					multiply_by_rational_with_rounding(
+						amount.saturated_into(),
+						*weight as u128,
+						total.max(1),
+						Rounding::Down,
+					)
+					.unwrap_or_default()
+					.saturated_into::<BalanceOf<T>>()
+				})
+				.collect::<Vec<_>>();
+			let remainder = parts.iter().fold(amount, |left, part| left.saturating_sub(*part));
+			if let Some(first) = weights.iter().position(|weight| !weight.is_zero()) {
+				parts[first].saturating_accrue(remainder);
+			}
+			parts
+		}
+
+		/// Take `amount` out of `nominations` in proportion to their stake, and return how much was
+		/// taken from each.
+		fn take_proportionally(
+			nominations: &mut BoundedVec<Nomination<T>, T::MaxNominations>,
+			amount: BalanceOf<T>,
+		) -> Vec<BalanceOf<T>> {
+			let total = nominations
+				.iter()
+				.fold(BalanceOf::<T>::zero(), |total, n| total.saturating_add(n.stake))
+				.saturated_into::<u128>();
+			let mut taken = nominations
+				.iter()
+				.map(|n| {
+					multiply_by_rational_with_rounding(
+						n.stake.saturated_into(),
+						amount.saturated_into(),
+						total,
+						Rounding::Down,
+					)
+					.unwrap_or_default()
+					.saturated_into::<BalanceOf<T>>()
+				})
+				.collect::<Vec<_>>();
+
+			// Rounding down leaves a bit to take from whoever still has some stake left.
+			let mut remainder = taken.iter().fold(amount, |left, t| left.saturating_sub(*t));
+			for (nomination, take) in nominations.iter_mut().zip(taken.iter_mut()) {
+				let extra = nomination.stake.saturating_sub(*take).min(remainder);
+				take.saturating_accrue(extra);
+				remainder.saturating_reduce(extra);
+				nomination.stake.saturating_reduce(*take);
+			}
+			taken
+		}
+
+		/// Whether other delegators delegate through `who`.
+		fn has_chained_delegators(who: &T::AccountId) -> bool {
+			ChainedDelegators::<T>::iter_key_prefix(who).next().is_some()
+		}
+
+		/// Remove the empty `delegation` of `who` along with its reverse index entries, and
+		/// release its deposit.
+		fn remove_delegation(who: &T::AccountId, delegation: &DelegationInfo<T>) {
+			Delegations::<T>::remove(who);
+			Payees::<T>::remove(who);
+			Self::remove_reverse_index(who, delegation);
+			// Releasing with best effort cannot fail.
+			let _ = T::NativeBalance::release_all(
+				&HoldReason::DelegationDeposit.into(),
+				who,
+				Precision::BestEffort,
+			);
+		}
+
+		fn remove_reverse_index(who: &T::AccountId, delegation: &DelegationInfo<T>) {
+			for nomination in delegation.nominations.iter() {
+				DelegatorsOf::<T>::remove(&nomination.validator, who);
+			}
+			if let [target] = delegation.targets.as_slice() {
+				ChainedDelegators::<T>::remove(target, who);
+			}
+		}
+
+		/// Queue `amount` of the stake of `who` towards `validator` for release after
+		/// [`Config::UnbondingPeriod`]. The funds stay on hold until then.
+		fn unbond(
+			who: &T::AccountId,
+			validator: &T::AccountId,
+			amount: BalanceOf<T>,
+		) -> DispatchResult {
+			let unlock_epoch = CurrentEpoch::<T>::get().saturating_add(T::UnbondingPeriod::get());
+			Unlocking::<T>::try_mutate(who, |chunks| -> DispatchResult {
+				// Stake unbonded in the same epoch from the same validator shares a chunk.
+				if let Some(chunk) = chunks
+					.iter_mut()
+					.find(|c| &c.validator == validator && c.unlock_epoch == unlock_epoch)
+				{
+					chunk.value.saturating_accrue(amount);
+					return Ok(())
+				}
+				chunks
+					.try_push(UnlockChunk {
+						validator: validator.clone(),
+						value: amount,
+						unlock_epoch,
+					})
+					.map_err(|_| Error::<T>::TooManyUnlockingChunks.into())
+			})?;
+			UnbondingFrom::<T>::insert(validator, who, ());
+			Ok(())
+		}
+
+		/// Change the stake of the nomination of `validator` by `delegator` from `old` to `new`,
+		/// along with the backing of `validator` if the nomination is counted.
+		///
+		/// A nomination that is not counted yet is counted if `validator` has room in
+		/// [`CountedDelegators`], or if it has more stake than the smallest counted nomination,
+		/// which is then oversubscribed instead. The rewards of `delegator` must have been settled
+		/// before.
+		fn update_stake(
+			validator: &T::AccountId,
+			delegator: &T::AccountId,
+			old: BalanceOf<T>,
+			new: BalanceOf<T>,
+		) {
+			// The nomination to evict is paid what it earned while counted. This comes first, as
+			// restaking the rewards grows it, possibly enough for another one to be evicted.
+			if !new.is_zero() && !Self::is_counted(validator, delegator) {
+				let mut settled = BTreeSet::new();
+				while let Some(evicted) = Self::next_evicted(validator, new) {
+					if !settled.insert(evicted.clone()) {
+						break
+					}
+					Self::settle_rewards_of(&evicted);
+				}
+			}
+
+			let mut counted = CountedDelegators::<T>::get(validator);
+			let mut info = Validators::<T>::get(validator).unwrap_or_default();
+			if let Some(index) = counted.iter().position(|(who, _)| who == delegator) {
+				info.total_backing = info.total_backing.saturating_sub(old).saturating_add(new);
+				if new.is_zero() {
+					counted.remove(index);
+				} else {
+					counted[index].1 = new;
+				}
+			} else if !new.is_zero() {
+				if counted.try_push((delegator.clone(), new)).is_ok() {
+					info.total_backing.saturating_accrue(new);
+				} else if let Some(smallest) = counted
+					.iter_mut()
+					.filter(|(_, stake)| *stake < new)
+					.min_by_key(|(_, stake)| *stake)
+				{
+					let (evicted, stake) = sp_std::mem::replace(smallest, (delegator.clone(), new));
+					info.total_backing =
+						info.total_backing.saturating_sub(stake).saturating_add(new);
+					Self::deposit_event(Event::Oversubscribed {
+						delegator: evicted,
+						validator: validator.clone(),
+					});
+				} else if new > old {
+					Self::deposit_event(Event::Oversubscribed {
+						delegator: delegator.clone(),
+						validator: validator.clone(),
+					});
+				}
+			}
+
+			if counted.is_empty() {
+				CountedDelegators::<T>::remove(validator);
+			} else {
+				CountedDelegators::<T>::insert(validator, counted);
+			}
+			Self::put_validator(validator, info);
+		}
+
+		/// The counted delegator of `validator` that a nomination with `stake` would replace, if
+		/// it has no room for more.
+		fn next_evicted(validator: &T::AccountId, stake: BalanceOf<T>) -> Option<T::AccountId> {
+			let counted = CountedDelegators::<T>::get(validator);
+			if counted.len() < T::MaxDelegatorsPerValidator::get() as usize {
+				return None
+			}
+			counted
+				.into_iter()
+				.filter(|(_, counted_stake)| *counted_stake < stake)
+				.min_by_key(|(_, counted_stake)| *counted_stake)
+				.map(|(who, _)| who)
+		}
+
+		/// Whether the nomination of `validator` by `delegator` is in [`CountedDelegators`].
+		fn is_counted(validator: &T::AccountId, delegator: &T::AccountId) -> bool {
+			CountedDelegators::<T>::get(validator).iter().any(|(who, _)| who == delegator)
+		}
+
+		/// The validators nominated by `delegator` without being counted towards their backing
+		/// and rewards, as they have [`Config::MaxDelegatorsPerValidator`] delegators with more
+		/// stake.
+		pub fn oversubscribed(delegator: &T::AccountId) -> Vec<T::AccountId> {
+			Delegations::<T>::get(delegator)
+				.map(|delegation| {
+					delegation
+						.nominations
+						.into_iter()
+						.filter(|n| {
+							!n.stake.is_zero() && !Self::is_counted(&n.validator, delegator)
+						})
+						.map(|n| n.validator)
+						.collect()
+				})
+				.unwrap_or_default()
+		}
+
+		/// Write back the record of `validator` and refresh its spot in the [`CandidateList`].
+		/// [`Chilled`] candidates are kept out of the list.
+		///
+		/// The record is removed once nothing backs the validator anymore.
+		fn put_validator(validator: &T::AccountId, info: ValidatorInfo<T>) {
+			let listed = info.is_candidate() && !Chilled::<T>::contains_key(validator);
+			CandidateList::<T>::set(validator, listed.then_some(info.total_backing));
+			if info.total_backing.is_zero() {
+				Validators::<T>::remove(validator);
+				CommissionBaseline::<T>::remove(validator);
+				Chilled::<T>::remove(validator);
+				Payees::<T>::remove(validator);
+			} else {
+				Validators::<T>::insert(validator, info);
+			}
+		}
+
+		/// Note which active validator the slot of block `n` is assigned to, and whether it
+		/// authored the block. Nothing is noted for blocks without a known author. Returns the
+		/// weight consumed.
+		fn note_slot(n: BlockNumberFor<T>) -> Weight {
+			let db = T::DbWeight::get();
+			let Some(author) = Self::find_author() else { return db.reads(1) };
+			let Some(expected) = Self::slot_author(n) else { return db.reads(2) };
+			let authored = author == expected;
+			SlotsOf::<T>::mutate(&expected, |record| {
+				record.expected.saturating_inc();
+				if authored {
+					record.authored.saturating_inc();
+				}
+			});
+
+			// Reading the slot schedule, and reading and writing the record of the validator.
+			db.reads_writes(4, 1)
+		}
+
+		/// Check the slots of the active validators in the epoch that is ending. Those that missed
+		/// more than [`Config::MissedSlotsThreshold`] of their slots are chilled, if still
+		/// candidates, and slashed by their share of missed slots of [`Config::MissedSlotsSlash`].
+		///
+		/// Unless [`Config::SlashDeferDuration`] is zero, the slash goes to [`UnappliedSlashes`],
+		/// to be applied once that many full epochs have passed.
+		///
+		/// The slot records are reset for the next epoch. Returns the weight consumed.
+		pub(crate) fn punish_missed_slots() -> Weight {
+			let db = T::DbWeight::get();
+			let epoch = CurrentEpoch::<T>::get();
+			let records = SlotsOf::<T>::drain().collect::<Vec<_>>();
+			// Reading `CurrentEpoch`, and taking every record.
+			let mut weight = db.reads_writes(1 + records.len() as u64, records.len() as u64);
+
+			let threshold = T::MissedSlotsThreshold::get();
+			for (who, SlotRecord { expected, authored }) in records {
+				let missed = Perbill::from_rational(expected.saturating_sub(authored), expected);
+				if missed <= threshold {
+					continue
+				}
+				let slash = T::MissedSlotsSlash::get() * missed;
+				Self::deposit_event(Event::Offence {
+					who: who.clone(),
+					epoch,
+					expected,
+					authored,
+					slash,
+				});
+				// Validators that unregistered during the epoch are only slashed.
+				weight.saturating_accrue(T::DbWeight::get().reads(1));
+				if Self::candidate(&who).is_ok() {
+					weight.saturating_accrue(Self::do_chill(&who));
+				}
+				weight.saturating_accrue(Self::defer_slash(who, slash, epoch));
+			}
+			weight
+		}
+
+		/// Queue the slash of `validator` for its offence in `epoch`, or apply it right away if
+		/// slashes are not deferred. Returns the weight consumed.
+		fn defer_slash(validator: T::AccountId, fraction: Perbill, epoch: EpochIndex) -> Weight {
+			let defer = T::SlashDeferDuration::get();
+			if defer.is_zero() || fraction.is_zero() {
+				return Self::slash_exposed(&validator, fraction, epoch)
+			}
+
+			let apply_at = epoch.saturating_add(1).saturating_add(defer);
+			let slash = UnappliedSlash { validator: validator.clone(), fraction, epoch };
+			let queued =
+				UnappliedSlashes::<T>::try_mutate(apply_at, |slashes| slashes.try_push(slash));
+			if queued.is_err() {
+				// Only happens if `MaxValidators` was lowered, letting more offenders through than
+				// fit. Better to slash right away than to let them off.
+				return Self::slash_exposed(&validator, fraction, epoch)
+			}
+			Self::deposit_event(Event::SlashDeferred { who: validator, fraction, epoch: apply_at });
+			T::DbWeight::get().reads_writes(1, 1)
+		}
+
+		/// Apply the [`UnappliedSlashes`] deferred to the epoch that is starting. Returns the
+		/// weight consumed.
+		pub(crate) fn apply_deferred_slashes() -> Weight {
+			let epoch = CurrentEpoch::<T>::get().saturating_add(1);
+			// Reading `CurrentEpoch` and taking the slashes.
+			let mut weight = T::DbWeight::get().reads_writes(2, 1);
+			for slash in UnappliedSlashes::<T>::take(epoch) {
+				weight.saturating_accrue(Self::slash_exposed(
+					&slash.validator,
+					slash.fraction,
+					slash.epoch,
+				));
+			}
+			weight
+		}
+
+		/// Take `who` out of the [`CandidateList`], so that it is not elected anymore. Returns the
+		/// weight consumed.
+		fn do_chill(who: &T::AccountId) -> Weight {
+			Chilled::<T>::insert(who, CurrentEpoch::<T>::get());
+			CandidateList::<T>::set(who, None);
+			Self::deposit_event(Event::Chilled { who: who.clone() });
+
+			// Reading `CurrentEpoch` and the node of `who`, and unlinking it from its bag.
+			T::DbWeight::get().reads_writes(4, 4)
+		}
+
+		/// Slash `fraction` of everything at stake behind `who`: its self stake, every counted
+		/// delegation towards it, and any stake still unbonding from it. Oversubscribed
+		/// delegations are not at stake.
+		///
+		/// Pending delegator rewards are paid out first, since they were earned before the
+		/// offence. The slashed funds are handed to [`Config::OnSlash`] as a single credit.
+		///
+		/// Delegations only lose the stake of their nomination of `who`. Slashed amounts are
+		/// rounded down. A delegation slashed down to nothing is removed, unless others delegate
+		/// through it.
+		///
+		/// Returns the weight consumed.
+		pub(crate) fn slash(who: &T::AccountId, fraction: Perbill) -> Weight {
+			if fraction.is_zero() {
+				return Weight::zero()
+			}
+			Self::slash_stake(who, SlashAmount::Fraction(fraction))
+		}
+
+		/// Slash `fraction` of what was at stake behind `who` in `epoch`, as in its [`Exposure`].
+		///
+		/// Each account loses `fraction` of its exposed stake, taken from its stake towards `who`
+		/// first and from its stake unbonding from `who` after that. Stake added since is not at
+		/// stake. Without an exposure, this falls back to [`Self::slash`]. Returns the weight
+		/// consumed.
+		pub(crate) fn slash_exposed(
+			who: &T::AccountId,
+			fraction: Perbill,
+			epoch: EpochIndex,
+		) -> Weight {
+			if fraction.is_zero() {
+				return Weight::zero()
+			}
+			let db = T::DbWeight::get();
+			let Some(exposure) = Exposures::<T>::get(epoch, who) else {
+				return db.reads(1).saturating_add(Self::slash(who, fraction))
+			};
+
+			let mut amounts = BTreeMap::new();
+			amounts.insert(who.clone(), fraction.mul_floor(exposure.own));
+			for (delegator, stake) in exposure.others {
+				amounts.insert(delegator, fraction.mul_floor(stake));
+			}
+			db.reads(1)
+				.saturating_add(Self::slash_stake(who, SlashAmount::Exposed(amounts)))
+		}
+
+		/// Slash the stake behind `who`, as much as `amounts` says. See [`Self::slash`]. Returns
+		/// the weight consumed.
+		fn slash_stake(who: &T::AccountId, mut amounts: SlashAmount<T>) -> Weight {
+			// Reading the record of `who`, and writing it back along with its spot in the list.
+			let (mut reads, mut writes) = (1u64, 4u64);
+
+			let mut slashed = fungible::Credit::<T::AccountId, T::NativeBalance>::zero();
+			let mut slashed_from = BTreeMap::<T::AccountId, BalanceOf<T>>::new();
+			let mut slash_held = |reason: HoldReason, account: &T::AccountId, amount| {
+				let (credit, _) = T::NativeBalance::slash(&reason.into(), account, amount);
+				let actual = credit.peek();
+				slashed.subsume(credit);
+				slashed_from.entry(account.clone()).or_default().saturating_accrue(actual);
+				actual
+			};
+
+			let delegators = match &amounts {
+				SlashAmount::Fraction(_) =>
+					CountedDelegators::<T>::get(who).into_iter().map(|(d, _)| d).collect(),
+				SlashAmount::Exposed(amounts) =>
+					amounts.keys().filter(|d| *d != who).cloned().collect::<Vec<_>>(),
+			};
+			// Rewards are paid out before reading the stake to slash, as they may be restaked.
+			for delegator in delegators.iter() {
+				Self::settle_rewards_of(delegator);
+			}
+
+			if let Some(mut info) = Validators::<T>::get(who) {
+				let amount =
+					slash_held(HoldReason::ValidatorBond, who, amounts.take(who, info.self_stake));
+				info.self_stake.saturating_reduce(amount);
+				info.total_backing.saturating_reduce(amount);
+
+				let mut counted = CountedDelegators::<T>::get(who);
+				// Reading each delegation twice, to pay out its rewards and to slash it, and
+				// writing it back both times along with the held balance and the rewards, then
+				// writing back the counted delegators.
+				reads.saturating_accrue(3 * delegators.len() as u64 + 2);
+				writes.saturating_accrue(4 * delegators.len() as u64 + 1);
+				for delegator in delegators.iter() {
+					let Some(mut delegation) = Delegations::<T>::get(delegator) else { continue };
+					let Some(nomination) =
+						delegation.nominations.iter_mut().find(|n| &n.validator == who)
+					else {
+						continue
+					};
+					let amount = slash_held(
+						HoldReason::Delegation,
+						delegator,
+						amounts.take(delegator, nomination.stake),
+					);
+					nomination.stake.saturating_reduce(amount);
+					delegation.amount.saturating_reduce(amount);
+					// Oversubscribed delegators exposed in the past are slashed all the same.
+					if let Some((_, stake)) = counted.iter_mut().find(|(d, _)| d == delegator) {
+						*stake = nomination.stake;
+						info.total_backing.saturating_reduce(amount);
+					}
+					if delegation.amount.is_zero() && !Self::has_chained_delegators(delegator) {
+						Self::remove_delegation(delegator, &delegation);
+					} else {
+						Delegations::<T>::insert(delegator, delegation);
+					}
+				}
+				counted.retain(|(_, stake)| !stake.is_zero());
+
+				if counted.is_empty() {
+					CountedDelegators::<T>::remove(who);
+				} else {
+					CountedDelegators::<T>::insert(who, counted);
+				}
+				Self::put_validator(who, info);
+			}
+
+			let unbonding = match &amounts {
+				// Only the accounts with stake unbonding from the validator are visited.
+				SlashAmount::Fraction(_) => UnbondingFrom::<T>::iter_key_prefix(who)
+					.map(|account| {
+						reads.saturating_accrue(2);
+						let chunks = Unlocking::<T>::get(&account);
+						(account, chunks)
+					})
+					.collect::<Vec<_>>(),
+				// Only the exposed accounts with some of their slash left are visited.
+				SlashAmount::Exposed(amounts) => amounts
+					.iter()
+					.filter(|(_, amount)| !amount.is_zero())
+					.map(|(account, _)| {
+						reads.saturating_inc();
+						(account.clone(), Unlocking::<T>::get(account))
+					})
+					.filter(|(_, chunks)| chunks.iter().any(|c| &c.validator == who))
+					.collect(),
+			};
+			// Writing the chunks, the held balance and the index entry of each unbonding account.
+			writes.saturating_accrue(3 * unbonding.len() as u64);
+			for (account, mut chunks) in unbonding {
+				let reason = if &account == who {
+					HoldReason::ValidatorBond
+				} else {
+					HoldReason::Delegation
+				};
+				for chunk in chunks.iter_mut().filter(|c| &c.validator == who) {
+					let amount = slash_held(reason, &account, amounts.take(&account, chunk.value));
+					chunk.value.saturating_reduce(amount);
+				}
+				chunks.retain(|c| !c.value.is_zero());
+				if !chunks.iter().any(|c| &c.validator == who) {
+					UnbondingFrom::<T>::remove(who, &account);
+				}
+				if chunks.is_empty() {
+					Unlocking::<T>::remove(&account);
+				} else {
+					Unlocking::<T>::insert(&account, chunks);
+				}
+			}
+
+			T::OnSlash::on_unbalanced(slashed);
+			for (who, amount) in slashed_from {
+				if !amount.is_zero() {
+					Self::deposit_event(Event::Slashed { who, amount });
+				}
+			}
+			T::DbWeight::get().reads_writes(reads, writes)
+		}
+
+		/// The top [`Config::MaxElectableCandidates`] of the [`CandidateList`] that are registered
+		/// per [`Config::ValidatorRegistration`], with their backing.
+		fn electable_candidates() -> Vec<(T::AccountId, BalanceOf<T>)> {
+			CandidateList::<T>::iter()
+				.filter(|(who, _)| T::ValidatorRegistration::is_registered(who))
+				.take(T::MaxElectableCandidates::get() as usize)
+				.collect()
+		}
+
+		/// The most reads needed to go through `count` candidates of the [`CandidateList`]: every
+		/// bag, and the node and registration of every candidate.
+		fn list_reads(count: usize) -> u64 {
+			(count as u64)
+				.saturating_mul(2)
+				.saturating_add(T::BagThresholds::get().len() as u64)
+				.saturating_add(1)
 		}
 	}
 }
@@ -217,9 +3108,7 @@ pub trait DoSlash<T: Config> {
 	fn do_slash(_who: T::AccountId, _amount: sp_runtime::Perbill);
 }
 impl<T: Config> DoSlash<T> for Pallet<T> {
-	fn do_slash(_who: T::AccountId, _amount: sp_runtime::Perbill) {
-		// lookup who
-		// get all delegators
-		// reduce who and delegators by amount
+	fn do_slash(who: T::AccountId, amount: sp_runtime::Perbill) {
+		Self::slash(&who, amount);
 	}
 }
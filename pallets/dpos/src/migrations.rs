@@ -0,0 +1,391 @@
+//! Storage migrations of pallet-dpos.
+
+pub mod v1 {
+	//! Turn the single target of every delegation into a list of nominations.
+	//!
+	//! Delegations of the old layout held nothing, and could name any account. Only those to a
+	//! validator candidate whose amount can now be held are kept.
+
+	use crate::{
+		BalanceOf, Config, DelegationInfo, Delegations, DelegatorsOf, HoldReason, Nomination,
+		Pallet, Validators,
+	};
+	use frame_support::{
+		migrations::VersionedMigration,
+		pallet_prelude::*,
+		traits::{fungible::MutateHold, UncheckedOnRuntimeUpgrade},
+	};
+	use sp_runtime::{traits::Zero, Saturating};
+	#[cfg(feature = "try-runtime")]
+	use sp_std::vec::Vec;
+
+	pub(crate) mod v0 {
+		use super::*;
+
+		/// A delegation to a single validator.
+		#[derive(Encode, Decode)]
+		pub struct DelegationInfo<T: Config> {
+			// The validator who is getting the delegation
+			pub who: T::AccountId,
+			// The amount being delegated
+			pub amount: BalanceOf<T>,
+		}
+
+		#[frame_support::storage_alias]
+		pub type Delegations<T: Config> = StorageMap<
+			Pallet<T>,
+			Blake2_128Concat,
+			<T as frame_system::Config>::AccountId,
+			DelegationInfo<T>,
+		>;
+	}
+
+	/// Version-unchecked part of [`MigrateV0ToV1`].
+	pub struct InnerMigrateV0ToV1<T>(PhantomData<T>);
+
+	impl<T: Config> UncheckedOnRuntimeUpgrade for InnerMigrateV0ToV1<T> {
+		fn on_runtime_upgrade() -> Weight {
+			let mut reads = 0u64;
+			let mut writes = 0u64;
+			Delegations::<T>::translate::<v0::DelegationInfo<T>, _>(|delegator, old| {
+				// Reading the delegation and the records of both accounts, and writing the
+				// delegation back.
+				reads.saturating_accrue(3);
+				writes.saturating_inc();
+				if old.amount.is_zero() || Validators::<T>::contains_key(&delegator) {
+					return None
+				}
+				let mut info = Validators::<T>::get(&old.who).filter(|info| info.is_candidate())?;
+				T::NativeBalance::hold(&HoldReason::Delegation.into(), &delegator, old.amount)
+					.ok()?;
+				// Writing the held balance, the reverse index and the record of the validator.
+				writes.saturating_accrue(3);
+
+				// Nothing was accrued before the delegation was held.
+				let nomination = Nomination {
+					validator: old.who.clone(),
+					stake: old.amount,
+					reward_per_share_paid: info.reward_per_share,
+				};
+				info.total_backing.saturating_accrue(old.amount);
+				DelegatorsOf::<T>::insert(&old.who, &delegator, ());
+				Validators::<T>::insert(&old.who, info);
+				Some(DelegationInfo {
+					targets: BoundedVec::truncate_from(vec![old.who]),
+					amount: old.amount,
+					nominations: BoundedVec::truncate_from(vec![nomination]),
+				})
+			});
+			T::DbWeight::get().reads_writes(reads, writes)
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<Vec<u8>, sp_runtime::TryRuntimeError> {
+			Ok((v0::Delegations::<T>::iter().count() as u32).encode())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade(state: Vec<u8>) -> Result<(), sp_runtime::TryRuntimeError> {
+			let before = u32::decode(&mut &state[..]).map_err(|_| "invalid pre-upgrade state")?;
+			ensure!(
+				Delegations::<T>::iter().count() as u32 <= before,
+				"delegations were added by the migration"
+			);
+			for (delegator, delegation) in Delegations::<T>::iter() {
+				for nomination in delegation.nominations {
+					ensure!(
+						DelegatorsOf::<T>::contains_key(&nomination.validator, &delegator),
+						"a delegation is missing from the reverse index"
+					);
+				}
+			}
+			Ok(())
+		}
+	}
+
+	/// Migrate [`Delegations`] from storage version 0 to 1, if the pallet is still at version 0.
+	pub type MigrateV0ToV1<T> = VersionedMigration<
+		0,
+		1,
+		InnerMigrateV0ToV1<T>,
+		Pallet<T>,
+		<T as frame_system::Config>::DbWeight,
+	>;
+}
+
+pub mod v2 {
+	//! Replace the bounded `TopValidators` list with the [`CandidateList`] of all candidates.
+
+	#[cfg(feature = "try-runtime")]
+	use crate::CandidateNodes;
+	use crate::{bags::CandidateList, Config, Pallet, Validators};
+	use frame_support::{
+		migrations::VersionedMigration, pallet_prelude::*, traits::UncheckedOnRuntimeUpgrade,
+	};
+	#[cfg(feature = "try-runtime")]
+	use sp_std::vec::Vec;
+
+	pub(crate) mod v1 {
+		use super::*;
+
+		#[frame_support::storage_alias]
+		pub type TopValidators<T: Config> = StorageValue<Pallet<T>, Vec<u8>, ValueQuery>;
+	}
+
+	/// Version-unchecked part of [`MigrateV1ToV2`].
+	pub struct InnerMigrateV1ToV2<T>(PhantomData<T>);
+
+	impl<T: Config> UncheckedOnRuntimeUpgrade for InnerMigrateV1ToV2<T> {
+		fn on_runtime_upgrade() -> Weight {
+			let mut reads = 0u64;
+			let mut listed = 0u64;
+			for (who, info) in Validators::<T>::iter() {
+				reads += 1;
+				if info.is_candidate() {
+					listed += 1;
+					CandidateList::<T>::set(&who, Some(info.total_backing));
+				}
+			}
+			v1::TopValidators::<T>::kill();
+			// Appending a candidate reads and writes its bag and the previous tail.
+			T::DbWeight::get()
+				.reads_writes(reads.saturating_add(listed * 2), listed.saturating_mul(3) + 1)
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<Vec<u8>, sp_runtime::TryRuntimeError> {
+			let candidates =
+				Validators::<T>::iter().filter(|(_, info)| info.is_candidate()).count();
+			Ok((candidates as u32).encode())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade(state: Vec<u8>) -> Result<(), sp_runtime::TryRuntimeError> {
+			let before = u32::decode(&mut &state[..]).map_err(|_| "invalid pre-upgrade state")?;
+			ensure!(
+				CandidateNodes::<T>::iter().count() as u32 == before,
+				"some candidates were not listed"
+			);
+			ensure!(!v1::TopValidators::<T>::exists(), "the old list was not removed");
+			Ok(())
+		}
+	}
+
+	/// Move the candidates from `TopValidators` to the [`CandidateList`], if the pallet is still
+	/// at version 1.
+	pub type MigrateV1ToV2<T> = VersionedMigration<
+		1,
+		2,
+		InnerMigrateV1ToV2<T>,
+		Pallet<T>,
+		<T as frame_system::Config>::DbWeight,
+	>;
+}
+
+pub mod v3 {
+	//! Count at most [`Config::MaxDelegatorsPerValidator`] delegators towards the backing of each
+	//! validator, the ones with the most stake.
+
+	use crate::{
+		bags::CandidateList, BalanceOf, Chilled, Config, CountedDelegators, Delegations,
+		DelegatorsOf, Pallet, Validators,
+	};
+	use frame_support::{
+		migrations::VersionedMigration, pallet_prelude::*, traits::UncheckedOnRuntimeUpgrade,
+		BoundedVec,
+	};
+	use sp_runtime::{traits::Zero, Saturating};
+	use sp_std::{cmp::Reverse, vec::Vec};
+
+	/// Version-unchecked part of [`MigrateV2ToV3`].
+	pub struct InnerMigrateV2ToV3<T>(PhantomData<T>);
+
+	impl<T: Config> UncheckedOnRuntimeUpgrade for InnerMigrateV2ToV3<T> {
+		fn on_runtime_upgrade() -> Weight {
+			let mut reads = 0u64;
+			let mut writes = 0u64;
+			let validators = Validators::<T>::iter().collect::<Vec<_>>();
+			for (validator, mut info) in validators {
+				let mut stakes = DelegatorsOf::<T>::iter_key_prefix(&validator)
+					.filter_map(|delegator| {
+						let stake = Delegations::<T>::get(&delegator)?
+							.nominations
+							.into_iter()
+							.find(|n| n.validator == validator)?
+							.stake;
+						(!stake.is_zero()).then_some((delegator, stake))
+					})
+					.collect::<Vec<_>>();
+				reads.saturating_accrue(1 + 2 * stakes.len() as u64);
+				// A stable sort, so that ties keep the order of the index.
+				stakes.sort_by_key(|(_, stake)| Reverse(*stake));
+
+				let max = T::MaxDelegatorsPerValidator::get() as usize;
+				// The oversubscribed delegators are paid what they earned so far, while they were
+				// still counted.
+				for (delegator, _) in stakes.iter().skip(max) {
+					Delegations::<T>::mutate(delegator, |delegation| {
+						let Some(delegation) = delegation else { return };
+						let nominations = delegation.nominations.iter_mut();
+						for nomination in nominations.filter(|n| n.validator == validator) {
+							let reward =
+								Pallet::<T>::accrued_since(nomination, info.reward_per_share);
+							Pallet::<T>::mint_reward(delegator, &validator, reward, None);
+							nomination.reward_per_share_paid = info.reward_per_share;
+						}
+					});
+					writes.saturating_accrue(2);
+				}
+				stakes.truncate(max);
+
+				let delegated = stakes
+					.iter()
+					.fold(BalanceOf::<T>::zero(), |total, (_, stake)| total.saturating_add(*stake));
+				info.total_backing = info.self_stake.saturating_add(delegated);
+				if info.is_candidate() && !Chilled::<T>::contains_key(&validator) {
+					CandidateList::<T>::set(&validator, Some(info.total_backing));
+				}
+				if !stakes.is_empty() {
+					CountedDelegators::<T>::insert(&validator, BoundedVec::truncate_from(stakes));
+				}
+				Validators::<T>::insert(&validator, info);
+				// Moving the candidate to another bag reads and writes both bags and neighbours.
+				reads.saturating_accrue(5);
+				writes.saturating_accrue(6);
+			}
+			T::DbWeight::get().reads_writes(reads, writes)
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<Vec<u8>, sp_runtime::TryRuntimeError> {
+			ensure!(CountedDelegators::<T>::iter().next().is_none(), "delegators already counted");
+			Ok(Vec::new())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade(_state: Vec<u8>) -> Result<(), sp_runtime::TryRuntimeError> {
+			let max = T::MaxDelegatorsPerValidator::get() as usize;
+			for (validator, info) in Validators::<T>::iter() {
+				let counted = CountedDelegators::<T>::get(&validator);
+				ensure!(counted.len() <= max, "too many delegators counted");
+				let delegated = counted
+					.iter()
+					.fold(BalanceOf::<T>::zero(), |total, (_, stake)| total.saturating_add(*stake));
+				ensure!(
+					info.total_backing == info.self_stake.saturating_add(delegated),
+					"only counted delegators back the validator"
+				);
+			}
+			Ok(())
+		}
+	}
+
+	/// Build [`CountedDelegators`] and recompute the backing of every validator, if the pallet is
+	/// still at version 2.
+	pub type MigrateV2ToV3<T> = VersionedMigration<
+		2,
+		3,
+		InnerMigrateV2ToV3<T>,
+		Pallet<T>,
+		<T as frame_system::Config>::DbWeight,
+	>;
+}
+
+pub mod v4 {
+	//! Count the [`Delegations`], which are capped at what the snapshot of an election can hold.
+
+	use crate::{Config, Delegations, Pallet};
+	use frame_support::{
+		migrations::VersionedMigration, pallet_prelude::*, traits::UncheckedOnRuntimeUpgrade,
+	};
+	#[cfg(feature = "try-runtime")]
+	use sp_std::vec::Vec;
+
+	/// Version-unchecked part of [`MigrateV3ToV4`].
+	pub struct InnerMigrateV3ToV4<T>(PhantomData<T>);
+
+	impl<T: Config> UncheckedOnRuntimeUpgrade for InnerMigrateV3ToV4<T> {
+		fn on_runtime_upgrade() -> Weight {
+			let count = Delegations::<T>::initialize_counter();
+			T::DbWeight::get().reads_writes(count.into(), 1)
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<Vec<u8>, sp_runtime::TryRuntimeError> {
+			Ok((Delegations::<T>::iter().count() as u32).encode())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade(state: Vec<u8>) -> Result<(), sp_runtime::TryRuntimeError> {
+			let before = u32::decode(&mut &state[..]).map_err(|_| "invalid pre-upgrade state")?;
+			ensure!(Delegations::<T>::count() == before, "the delegations were miscounted");
+			Ok(())
+		}
+	}
+
+	/// Initialize the counter of [`Delegations`], if the pallet is still at version 3.
+	pub type MigrateV3ToV4<T> = VersionedMigration<
+		3,
+		4,
+		InnerMigrateV3ToV4<T>,
+		Pallet<T>,
+		<T as frame_system::Config>::DbWeight,
+	>;
+}
+
+pub mod v5 {
+	//! Index the [`Unlocking`] stake by the validator it was unbonded from, so that slashing a
+	//! validator only visits the accounts unbonding from it.
+
+	use crate::{Config, Pallet, UnbondingFrom, Unlocking};
+	use frame_support::{
+		migrations::VersionedMigration, pallet_prelude::*, traits::UncheckedOnRuntimeUpgrade,
+	};
+	#[cfg(feature = "try-runtime")]
+	use sp_std::vec::Vec;
+
+	/// Version-unchecked part of [`MigrateV4ToV5`].
+	pub struct InnerMigrateV4ToV5<T>(PhantomData<T>);
+
+	impl<T: Config> UncheckedOnRuntimeUpgrade for InnerMigrateV4ToV5<T> {
+		fn on_runtime_upgrade() -> Weight {
+			let mut reads = 0u64;
+			let mut writes = 0u64;
+			for (who, chunks) in Unlocking::<T>::iter() {
+				reads += 1;
+				for chunk in chunks {
+					UnbondingFrom::<T>::insert(&chunk.validator, &who, ());
+					writes += 1;
+				}
+			}
+			T::DbWeight::get().reads_writes(reads, writes)
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<Vec<u8>, sp_runtime::TryRuntimeError> {
+			Ok(Vec::new())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade(_: Vec<u8>) -> Result<(), sp_runtime::TryRuntimeError> {
+			for (who, chunks) in Unlocking::<T>::iter() {
+				for chunk in chunks {
+					ensure!(
+						UnbondingFrom::<T>::contains_key(&chunk.validator, &who),
+						"unbonding stake was not indexed"
+					);
+				}
+			}
+			Ok(())
+		}
+	}
+
+	/// Build the [`UnbondingFrom`] index, if the pallet is still at version 4.
+	pub type MigrateV4ToV5<T> = VersionedMigration<
+		4,
+		5,
+		InnerMigrateV4ToV5<T>,
+		Pallet<T>,
+		<T as frame_system::Config>::DbWeight,
+	>;
+}
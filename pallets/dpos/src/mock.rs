@@ -1,18 +1,34 @@
-use crate::{self as pallet_dpos, ReportNewValidatorSet};
+use crate::{
+	self as pallet_dpos,
+	election::{ElectionError, ElectionStrategy, SequentialPhragmen, Supports, TopBacking, Voter},
+	payout::IdealStakingCurve,
+	DigestAuthor, ReportNewValidatorSet,
+};
 use frame_support::{
 	derive_impl, parameter_types,
-	traits::{ConstU128, ConstU16, ConstU32, ConstU64, FindAuthor, Hooks},
+	traits::{
+		tokens::imbalance::ResolveTo, ConstU128, ConstU16, ConstU32, ConstU64, FindAuthor, Hooks,
+		Randomness, ValidatorRegistration,
+	},
 };
 use frame_system::pallet_prelude::BlockNumberFor;
-use sp_core::H256;
+use sp_core::{
+	offchain::{
+		testing::{PoolState, TestOffchainExt, TestTransactionPoolExt},
+		OffchainDbExt, OffchainWorkerExt, TransactionPoolExt,
+	},
+	H256,
+};
 use sp_runtime::{
-	traits::{BlakeTwo256, IdentityLookup},
-	BuildStorage,
+	testing::TestXt,
+	traits::{BlakeTwo256, Hash, IdentityLookup},
+	BuildStorage, Perbill,
 };
+use std::sync::Arc;
 
 type Block = frame_system::mocking::MockBlock<Test>;
-type Balance = u128;
-type AccountId = u64;
+pub type Balance = u128;
+pub type AccountId = u64;
 
 // Configure a mock runtime to test the pallet. We use the simpler syntax here.
 frame_support::construct_runtime! {
@@ -32,7 +48,8 @@ impl frame_system::Config for Test {
 	type BaseCallFilter = frame_support::traits::Everything;
 	type BlockWeights = ();
 	type BlockLength = ();
-	type DbWeight = ();
+	// Use a real database weight, so that we can check the weight of our hooks.
+	type DbWeight = frame_support::weights::constants::RocksDbWeight;
 	type RuntimeOrigin = RuntimeOrigin;
 	type RuntimeCall = RuntimeCall;
 	type Nonce = u64;
@@ -65,7 +82,8 @@ impl pallet_balances::Config for Test {
 	type MaxLocks = ConstU32<10>;
 	type MaxReserves = ();
 	type ReserveIdentifier = [u8; 8];
-	type RuntimeHoldReason = ();
+	// Need to update the `RuntimeHoldReason` expected by the balances pallet.
+	type RuntimeHoldReason = RuntimeHoldReason;
 	type FreezeIdentifier = ();
 	type MaxFreezes = ConstU32<10>;
 }
@@ -74,32 +92,199 @@ parameter_types! {
 	// static makes MaxValidators configurable on the fly.
 	pub static MaxValidators: u32 = 10;
 	pub static Author: AccountId = 7;
+	pub static BlockReward: Balance = 100;
+	pub static MaxCommissionChange: Perbill = Perbill::from_percent(10);
+	pub static UnbondingPeriod: u32 = 2;
+	pub static MinValidatorBond: Balance = 1;
+	pub static MinDelegation: Balance = 1;
+	pub static DelegationDeposit: Balance = 0;
+	pub static MaxDelegatorsPerValidator: u32 = 64;
+	// No inflation unless a test turns it on, so that only the block reward is paid.
+	pub static MinInflation: Perbill = Perbill::zero();
+	pub static MaxInflation: Perbill = Perbill::zero();
+	pub static IdealStake: Perbill = Perbill::from_percent(50);
+	pub static Falloff: Perbill = Perbill::from_percent(25);
+	pub static EpochsPerYear: u32 = 1;
+	pub const TreasuryAccount: AccountId = 99;
+	pub static UsePhragmen: bool = false;
+	pub static UnsignedPhase: u64 = 10;
+	pub static SnapshotPages: u32 = 2;
+	pub static VotersPerPage: u32 = 2;
+	pub static MaxElectableCandidates: u32 = 100;
+	pub static StakeWeightedSlots: bool = false;
+	pub static ElectAhead: bool = false;
+	// Candidates without session keys, that cannot be elected.
+	pub static Unregistered: Vec<AccountId> = vec![];
+	// Offences are off unless a test turns them on.
+	pub static MissedSlotsThreshold: Perbill = Perbill::one();
+	pub static MissedSlotsSlash: Perbill = Perbill::from_percent(10);
+	pub static SlashDeferDuration: u32 = 0;
+	pub static HistoryDepth: u32 = 4;
+	pub static RandomSeed: H256 = H256::repeat_byte(42);
+	pub const BagThresholds: &'static [Balance] = &[10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 1000];
+	// The last validator set reported by the pallet.
+	pub static ReportedValidators: Vec<AccountId> = vec![];
 }
 
 pub struct DynamicAuthor;
 impl FindAuthor<AccountId> for DynamicAuthor {
-	fn find_author<'a, I>(_: I) -> Option<AccountId>
+	fn find_author<'a, I>(digests: I) -> Option<AccountId>
 	where
 		I: 'a + IntoIterator<Item = ([u8; 4], &'a [u8])>,
 	{
-		Some(Author::get())
+		// Blocks with a digest naming their author take it over the one set by the test.
+		DigestAuthor::<Test>::find_author(digests).or(Some(Author::get()))
+	}
+}
+
+pub struct TestRandomness;
+impl Randomness<H256, BlockNumberFor<Test>> for TestRandomness {
+	fn random(subject: &[u8]) -> (H256, BlockNumberFor<Test>) {
+		(BlakeTwo256::hash_of(&(RandomSeed::get(), subject)), 0)
 	}
 }
 
-pub struct DoNothing;
-impl ReportNewValidatorSet<AccountId> for DoNothing {
-	fn report_new_validator_set(_: Vec<AccountId>) {}
+pub struct StoreNewValidatorSet;
+impl ReportNewValidatorSet<AccountId> for StoreNewValidatorSet {
+	fn report_new_validator_set(new_set: Vec<AccountId>) {
+		ReportedValidators::set(new_set);
+	}
+}
+
+pub struct MockRegistration;
+impl ValidatorRegistration<AccountId> for MockRegistration {
+	fn is_registered(who: &AccountId) -> bool {
+		!Unregistered::get().contains(who)
+	}
+}
+
+pub type Extrinsic = TestXt<RuntimeCall, ()>;
+
+impl<LocalCall> frame_system::offchain::SendTransactionTypes<LocalCall> for Test
+where
+	RuntimeCall: From<LocalCall>,
+{
+	type OverarchingCall = RuntimeCall;
+	type Extrinsic = Extrinsic;
+}
+
+pub struct MockElection;
+impl ElectionStrategy<AccountId> for MockElection {
+	fn elect(
+		to_elect: usize,
+		candidates: Vec<AccountId>,
+		voters: Vec<Voter<AccountId>>,
+	) -> Result<Supports<AccountId>, ElectionError> {
+		if UsePhragmen::get() {
+			SequentialPhragmen::<Perbill>::elect(to_elect, candidates, voters)
+		} else {
+			TopBacking::elect(to_elect, candidates, voters)
+		}
+	}
 }
 
 impl pallet_dpos::Config for Test {
 	type RuntimeEvent = RuntimeEvent;
 	type NativeBalance = Balances;
+	type RuntimeHoldReason = RuntimeHoldReason;
 	type MaxValidators = MaxValidators;
 	type FindAuthor = DynamicAuthor;
-	type ReportNewValidatorSet = DoNothing;
+	type Randomness = TestRandomness;
+	type StakeWeightedSlots = StakeWeightedSlots;
+	type ReportNewValidatorSet = StoreNewValidatorSet;
+	type ElectAhead = ElectAhead;
+	type ValidatorRegistration = MockRegistration;
 	// Assuming blocks happen every 6 seconds, this will be 600 seconds, approximately 10 minutes.
 	// But this is all just test config, but gives you an idea how this is all CONFIGURABLE
 	type EpochDuration = ConstU64<100>;
+	type BlockReward = BlockReward;
+	type EraPayout =
+		IdealStakingCurve<MinInflation, MaxInflation, IdealStake, Falloff, EpochsPerYear>;
+	type RewardRemainder = ResolveTo<TreasuryAccount, Balances>;
+	type MaxCommissionChange = MaxCommissionChange;
+	type UnbondingPeriod = UnbondingPeriod;
+	type MinValidatorBond = MinValidatorBond;
+	type MinDelegation = MinDelegation;
+	type DelegationDeposit = DelegationDeposit;
+	type MaxDelegatorsPerValidator = MaxDelegatorsPerValidator;
+	type MaxUnlockingChunks = ConstU32<4>;
+	type OnSlash = ();
+	type MaxDelegationDepth = ConstU32<3>;
+	type MaxNominations = ConstU32<3>;
+	type ElectionStrategy = MockElection;
+	type UnsignedPhase = UnsignedPhase;
+	type UnsignedPriority = ConstU64<100>;
+	type SnapshotPages = SnapshotPages;
+	type VotersPerPage = VotersPerPage;
+	type MaxElectableCandidates = MaxElectableCandidates;
+	type BagThresholds = BagThresholds;
+	type MissedSlotsThreshold = MissedSlotsThreshold;
+	type MissedSlotsSlash = MissedSlotsSlash;
+	type SlashDeferDuration = SlashDeferDuration;
+	type HistoryDepth = HistoryDepth;
+	type SlashCancelOrigin = frame_system::EnsureRoot<AccountId>;
+}
+
+pub struct StateBuilder {
+	pub initial_balances: Vec<(AccountId, Balance)>,
+}
+
+impl Default for StateBuilder {
+	fn default() -> Self {
+		Self { initial_balances: vec![(1, 100), (2, 100), (3, 100), (10, 100), (11, 100)] }
+	}
+}
+
+impl StateBuilder {
+	pub(crate) fn with_balance(mut self, acc: AccountId, balance: Balance) -> Self {
+		self.initial_balances.push((acc, balance));
+		self
+	}
+
+	fn build(self) -> sp_io::TestExternalities {
+		let system = frame_system::GenesisConfig::<Test>::default();
+		let balances = pallet_balances::GenesisConfig::<Test> { balances: self.initial_balances };
+		RuntimeGenesisConfig { system, balances }.build_storage().unwrap().into()
+	}
+
+	pub(crate) fn build_and_execute(self, test: impl FnOnce()) {
+		Self::execute(self.build(), test)
+	}
+
+	/// Like `build_and_execute`, but with offchain workers able to submit transactions into the
+	/// returned pool.
+	pub(crate) fn build_offchain_and_execute(
+		self,
+		test: impl FnOnce(Arc<parking_lot::RwLock<PoolState>>),
+	) {
+		let mut ext = self.build();
+		let (offchain, _) = TestOffchainExt::new();
+		let (pool, pool_state) = TestTransactionPoolExt::new();
+		ext.register_extension(OffchainDbExt::new(offchain.clone()));
+		ext.register_extension(OffchainWorkerExt::new(offchain));
+		ext.register_extension(TransactionPoolExt::new(pool));
+		Self::execute(ext, || test(pool_state))
+	}
+
+	fn execute(mut ext: sp_io::TestExternalities, test: impl FnOnce()) {
+		ext.execute_with(|| {
+			// Go past genesis block so events get deposited.
+			System::set_block_number(1);
+			test();
+			Dpos::do_try_state();
+		});
+	}
+}
+
+/// The events emitted by this pallet, since the last call to this function.
+pub fn dpos_events() -> Vec<crate::Event<Test>> {
+	let events = System::events()
+		.into_iter()
+		.map(|r| r.event)
+		.filter_map(|e| if let RuntimeEvent::Dpos(inner) = e { Some(inner) } else { None })
+		.collect::<Vec<_>>();
+	System::reset_events();
+	events
 }
 
 pub fn new_test_ext() -> sp_io::TestExternalities {
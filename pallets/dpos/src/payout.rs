@@ -0,0 +1,87 @@
+//! How much is minted at the end of every epoch, and how it is split between the validators and
+//! the rest of the network.
+
+use frame_support::traits::tokens::Balance;
+use sp_runtime::{traits::Get, Perbill, Saturating};
+use sp_std::marker::PhantomData;
+
+/// The payout of an epoch, as a function of the stake backing the validators.
+pub trait EraPayout<Balance> {
+	/// The amount to mint at the end of an epoch in which the active validators were backed by
+	/// `total_staked`, out of `total_issuance`.
+	///
+	/// Returns the payout of the validators and their delegators, and the remainder, which goes
+	/// to [`crate::Config::RewardRemainder`].
+	fn era_payout(total_staked: Balance, total_issuance: Balance) -> (Balance, Balance);
+}
+
+/// No payout at all, leaving [`crate::Config::BlockReward`] as the only reward.
+impl<Balance: Default> EraPayout<Balance> for () {
+	fn era_payout(_total_staked: Balance, _total_issuance: Balance) -> (Balance, Balance) {
+		(Default::default(), Default::default())
+	}
+}
+
+/// An inflation curve rewarding stakers the most when `IdealStake` of the issuance is staked.
+///
+/// The yearly inflation paid to validators grows linearly from `MinInflation` when nothing is
+/// staked to `MaxInflation` at the ideal staking rate. Past it, it falls linearly back to
+/// `MinInflation`, which is reached once `Falloff` more of the issuance is staked, to push
+/// stakers towards the ideal rate.
+///
+/// The remainder is the rest of `MaxInflation`, so that the issuance always grows at that rate.
+/// Both are paid out over `EpochsPerYear` epochs.
+pub struct IdealStakingCurve<MinInflation, MaxInflation, IdealStake, Falloff, EpochsPerYear>(
+	PhantomData<(MinInflation, MaxInflation, IdealStake, Falloff, EpochsPerYear)>,
+);
+
+impl<MinInflation, MaxInflation, IdealStake, Falloff, EpochsPerYear>
+	IdealStakingCurve<MinInflation, MaxInflation, IdealStake, Falloff, EpochsPerYear>
+where
+	MinInflation: Get<Perbill>,
+	MaxInflation: Get<Perbill>,
+	IdealStake: Get<Perbill>,
+	Falloff: Get<Perbill>,
+{
+	/// The yearly inflation paid to validators when `staked` of the issuance is staked.
+	pub fn inflation(staked: Perbill) -> Perbill {
+		let (min, max) = (MinInflation::get(), MaxInflation::get().max(MinInflation::get()));
+		let ideal = IdealStake::get();
+		let range = max.saturating_sub(min);
+		if staked <= ideal {
+			let progress = if ideal.is_zero() {
+				Perbill::one()
+			} else {
+				Perbill::from_rational(staked.deconstruct(), ideal.deconstruct())
+			};
+			min.saturating_add(range * progress)
+		} else {
+			let excess = staked.saturating_sub(ideal);
+			let decline = if Falloff::get().is_zero() {
+				Perbill::one()
+			} else {
+				Perbill::from_rational(excess.deconstruct(), Falloff::get().deconstruct())
+			};
+			max.saturating_sub(range * decline)
+		}
+	}
+}
+
+impl<B, MinInflation, MaxInflation, IdealStake, Falloff, EpochsPerYear> EraPayout<B>
+	for IdealStakingCurve<MinInflation, MaxInflation, IdealStake, Falloff, EpochsPerYear>
+where
+	B: Balance,
+	MinInflation: Get<Perbill>,
+	MaxInflation: Get<Perbill>,
+	IdealStake: Get<Perbill>,
+	Falloff: Get<Perbill>,
+	EpochsPerYear: Get<u32>,
+{
+	fn era_payout(total_staked: B, total_issuance: B) -> (B, B) {
+		let epochs = B::from(EpochsPerYear::get().max(1));
+		let staked = Perbill::from_rational(total_staked, total_issuance);
+		let payout = Self::inflation(staked).mul_floor(total_issuance) / epochs;
+		let max_payout = MaxInflation::get().mul_floor(total_issuance) / epochs;
+		(payout, max_payout.saturating_sub(payout))
+	}
+}
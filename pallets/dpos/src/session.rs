@@ -0,0 +1,77 @@
+//! Integration with `pallet-session`.
+//!
+//! The pallet is the [`SessionManager`] of the runtime, so that every session rotation pulls the
+//! latest elected validators. With [`Config::ElectAhead`], those are the [`QueuedValidators`],
+//! which become the [`ActiveValidators`] along with the session the validators are queued for. It
+//! is also a [`OneSessionHandler`] for its own key type, keeping the session keys of the validators
+//! in [`Authorities`].
+
+use crate::{ActiveValidators, Authorities, Config, Pallet, QueuedValidators};
+use frame_support::{
+	traits::{Get, OneSessionHandler},
+	BoundedVec,
+};
+use pallet_session::SessionManager;
+use sp_runtime::BoundToRuntimeAppPublic;
+use sp_std::prelude::*;
+
+/// The session key of the validators, under the `dpos` key type.
+pub mod crypto {
+	use sp_runtime::{
+		app_crypto::{app_crypto, sr25519},
+		KeyTypeId,
+	};
+
+	/// The key type of the session keys of validators.
+	pub const KEY_TYPE: KeyTypeId = KeyTypeId(*b"dpos");
+
+	app_crypto!(sr25519, KEY_TYPE);
+}
+
+/// The public session key of a validator.
+pub type AuthorityId = crypto::Public;
+
+impl<T: Config> SessionManager<T::AccountId> for Pallet<T> {
+	/// The validators of the last election, to be queued by the session pallet.
+	///
+	/// Returns `None` before the first election, so that the genesis validators of the session
+	/// pallet are kept.
+	fn new_session(_new_index: u32) -> Option<Vec<T::AccountId>> {
+		let validators = if T::ElectAhead::get() {
+			QueuedValidators::<T>::get()
+		} else {
+			ActiveValidators::<T>::get()
+		};
+		(!validators.is_empty()).then(|| validators.into_inner())
+	}
+
+	fn end_session(_end_index: u32) {}
+
+	fn start_session(_start_index: u32) {}
+}
+
+impl<T: Config> BoundToRuntimeAppPublic for Pallet<T> {
+	type Public = AuthorityId;
+}
+
+impl<T: Config> OneSessionHandler<T::AccountId> for Pallet<T> {
+	type Key = AuthorityId;
+
+	fn on_genesis_session<'a, I>(validators: I)
+	where
+		I: 'a + Iterator<Item = (&'a T::AccountId, Self::Key)>,
+	{
+		Authorities::<T>::put(BoundedVec::truncate_from(validators.map(|(_, k)| k).collect()));
+	}
+
+	fn on_new_session<'a, I>(changed: bool, validators: I, _queued_validators: I)
+	where
+		I: 'a + Iterator<Item = (&'a T::AccountId, Self::Key)>,
+	{
+		if changed {
+			Authorities::<T>::put(BoundedVec::truncate_from(validators.map(|(_, k)| k).collect()));
+		}
+	}
+
+	fn on_disabled(_validator_index: u32) {}
+}
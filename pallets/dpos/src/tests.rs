@@ -1,5 +1,63 @@
-use crate::{mock::*, *};
-use frame_support::assert_ok;
+use crate::{bags::CandidateList, mock::*, *};
+use frame_support::{
+	assert_noop, assert_ok,
+	dispatch::Pays,
+	traits::{
+		fungible::{Inspect, InspectHold},
+		FindAuthor,
+	},
+	BoundedVec,
+};
+use sp_core::H256;
+use sp_runtime::{traits::Zero, FixedU128, Perbill, TokenError};
+
+fn held(reason: HoldReason, who: AccountId) -> Balance {
+	<Test as Config>::NativeBalance::balance_on_hold(&reason.into(), &who)
+}
+
+fn candidate_list() -> Vec<(AccountId, Balance)> {
+	CandidateList::<Test>::iter().collect()
+}
+
+fn single_delegation(validator: AccountId, amount: Balance) -> DelegationInfo<Test> {
+	let nomination = Nomination { validator, stake: amount, reward_per_share_paid: Zero::zero() };
+	DelegationInfo {
+		targets: BoundedVec::truncate_from(vec![validator]),
+		amount,
+		nominations: BoundedVec::truncate_from(vec![nomination]),
+	}
+}
+
+/// Make `who` the author of the coming blocks, with every slot assigned to it.
+fn author_every_slot(who: AccountId) {
+	Author::set(who);
+	ActiveValidators::<Test>::put(BoundedVec::truncate_from(vec![who]));
+}
+
+/// Elect validators 1 and 2, then run to the last block of the epoch with 1 authoring 20 of its
+/// 50 slots, missing 60% of them. 1 is backed by 20 of its own and 30 from 10.
+fn miss_slots_until_epoch_end() {
+	MissedSlotsThreshold::set(Perbill::from_percent(50));
+	MissedSlotsSlash::set(Perbill::from_percent(10));
+	assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+	assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 60, Perbill::zero()));
+	assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+	run_to_block(100);
+	assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![2, 1]);
+
+	for n in 101..200 {
+		Author::set(if n % 2 == 0 || n > 140 { 2 } else { 1 });
+		run_to_block(n);
+	}
+	dpos_events();
+}
+
+fn slashed_events() -> Vec<Event<Test>> {
+	dpos_events()
+		.into_iter()
+		.filter(|e| matches!(e, Event::Slashed { .. }))
+		.collect()
+}
 
 #[test]
 fn check_based_on_max_validators() {
@@ -45,3 +103,2187 @@ fn test_custom_author_in_test() {
 		Some(8)
 	);
 }
+
+#[test]
+fn register_validator_works() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 40, Perbill::zero()));
+
+		assert_eq!(
+			Validators::<Test>::get(1),
+			Some(ValidatorInfo { self_stake: 40, total_backing: 40, ..Default::default() })
+		);
+		assert_eq!(held(HoldReason::ValidatorBond, 1), 40);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&1), 60);
+		assert_eq!(candidate_list(), vec![(1, 40)]);
+		assert_eq!(
+			dpos_events(),
+			vec![Event::ValidatorRegistered {
+				who: 1,
+				self_stake: 40,
+				commission: Perbill::zero()
+			}]
+		);
+
+		// Cannot register twice.
+		assert_noop!(
+			Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()),
+			Error::<Test>::AlreadyValidator
+		);
+	});
+}
+
+#[test]
+fn register_validator_fails_for_bad_bond() {
+	StateBuilder::default().with_balance(42, 5).build_and_execute(|| {
+		assert_noop!(
+			Dpos::register_validator(RuntimeOrigin::signed(1), 0, Perbill::zero()),
+			Error::<Test>::InsufficientBond
+		);
+		assert_noop!(
+			Dpos::register_validator(RuntimeOrigin::signed(42), 10, Perbill::zero()),
+			TokenError::FundsUnavailable
+		);
+	});
+}
+
+#[test]
+fn minimum_bonds_are_enforced() {
+	StateBuilder::default().build_and_execute(|| {
+		MinValidatorBond::set(10);
+		MinDelegation::set(20);
+		assert_noop!(
+			Dpos::register_validator(RuntimeOrigin::signed(1), 9, Perbill::zero()),
+			Error::<Test>::InsufficientBond
+		);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+
+		assert_noop!(
+			Dpos::delegate(RuntimeOrigin::signed(10), 1, 19),
+			Error::<Test>::InsufficientDelegation
+		);
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+		// A partial decrease has to leave the minimum, leaving altogether is fine.
+		assert_noop!(
+			Dpos::decrease_delegation(RuntimeOrigin::signed(10), 11),
+			Error::<Test>::InsufficientDelegation
+		);
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(10), 10));
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(10), 20));
+		assert!(!Delegations::<Test>::contains_key(10));
+	});
+}
+
+#[test]
+fn delegations_hold_a_deposit() {
+	StateBuilder::default().build_and_execute(|| {
+		DelegationDeposit::set(5);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_noop!(
+			Dpos::delegate(RuntimeOrigin::signed(10), 1, 99),
+			TokenError::FundsUnavailable
+		);
+
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+		assert_eq!(held(HoldReason::DelegationDeposit, 10), 5);
+		assert_eq!(held(HoldReason::Delegation, 10), 30);
+
+		// The deposit is released with the delegation, the stake unbonds as usual.
+		assert_ok!(Dpos::undelegate(RuntimeOrigin::signed(10)));
+		assert_eq!(held(HoldReason::DelegationDeposit, 10), 0);
+		assert_eq!(held(HoldReason::Delegation, 10), 30);
+	});
+}
+
+#[test]
+fn dust_delegations_can_be_reaped() {
+	StateBuilder::default().build_and_execute(|| {
+		MinDelegation::set(5);
+		DelegationDeposit::set(5);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 10));
+		assert_noop!(
+			Dpos::reap_dust_delegation(RuntimeOrigin::signed(11), 10),
+			Error::<Test>::NotDust
+		);
+		assert_noop!(
+			Dpos::reap_dust_delegation(RuntimeOrigin::signed(11), 2),
+			Error::<Test>::NotDelegator
+		);
+
+		Dpos::slash(&1, Perbill::from_percent(60));
+		assert_eq!(Delegations::<Test>::get(10).unwrap().amount, 4);
+		dpos_events();
+
+		assert_ok!(Dpos::reap_dust_delegation(RuntimeOrigin::signed(11), 10));
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Undelegated { delegator: 10, amount: 4 },
+				Event::DelegationReaped { delegator: 10, reaper: 11 },
+			]
+		);
+		assert!(!Delegations::<Test>::contains_key(10));
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 4);
+		assert_eq!(held(HoldReason::DelegationDeposit, 10), 0);
+		// What is left still unbonds, and can still be slashed until then.
+		assert_eq!(held(HoldReason::Delegation, 10), 4);
+		assert_eq!(Unlocking::<Test>::get(10).len(), 1);
+	});
+}
+
+#[test]
+fn oversubscribed_delegators_are_not_counted() {
+	StateBuilder::default().with_balance(12, 100).build_and_execute(|| {
+		MaxDelegatorsPerValidator::set(2);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 30));
+		dpos_events();
+
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(12), 1, 10));
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Oversubscribed { delegator: 12, validator: 1 },
+				Event::Delegated { delegator: 12, amount: 10 },
+				Event::Nominated { delegator: 12, targets: vec![1] },
+			]
+		);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 100);
+		assert_eq!(CountedDelegators::<Test>::get(1).to_vec(), vec![(10, 50), (11, 30)]);
+		assert_eq!(Dpos::oversubscribed(&12), vec![1]);
+		assert!(Dpos::oversubscribed(&10).is_empty());
+
+		// Oversubscribed delegators earn nothing.
+		author_every_slot(1);
+		run_to_block(2);
+		assert_eq!(Dpos::pending_rewards(&10), 50);
+		assert_eq!(Dpos::pending_rewards(&12), 0);
+
+		// Nor are they at stake.
+		dpos_events();
+		Dpos::slash(&1, Perbill::from_percent(50));
+		assert_eq!(Delegations::<Test>::get(12).unwrap().amount, 10);
+		assert_eq!(Delegations::<Test>::get(11).unwrap().amount, 15);
+		assert_eq!(held(HoldReason::Delegation, 12), 10);
+	});
+}
+
+#[test]
+fn larger_delegations_evict_the_smallest_counted() {
+	StateBuilder::default().with_balance(12, 100).build_and_execute(|| {
+		MaxDelegatorsPerValidator::set(2);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 30));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(12), 1, 10));
+		author_every_slot(1);
+		run_to_block(2);
+		dpos_events();
+
+		// Topping up past the smallest counted delegation takes its place, after paying out
+		// what it earned while counted.
+		assert_ok!(Dpos::increase_delegation(RuntimeOrigin::signed(12), 40));
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Rewarded { who: 11, validator: 1, amount: 30 },
+				Event::Oversubscribed { delegator: 11, validator: 1 },
+				Event::Delegated { delegator: 12, amount: 40 },
+			]
+		);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 120);
+		assert_eq!(CountedDelegators::<Test>::get(1).to_vec(), vec![(10, 50), (12, 50)]);
+		assert_eq!(Dpos::oversubscribed(&11), vec![1]);
+		assert!(Dpos::oversubscribed(&12).is_empty());
+		assert_eq!(Dpos::pending_rewards(&11), 0);
+
+		// Leaving frees the slot, which the next delegation to grow takes.
+		assert_ok!(Dpos::undelegate(RuntimeOrigin::signed(10)));
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 70);
+		assert_ok!(Dpos::increase_delegation(RuntimeOrigin::signed(11), 5));
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 105);
+		assert_eq!(CountedDelegators::<Test>::get(1).to_vec(), vec![(12, 50), (11, 35)]);
+	});
+}
+
+#[test]
+fn shrunk_counted_delegations_can_be_swapped_out() {
+	use frame_support::dispatch::Pays;
+	StateBuilder::default().with_balance(12, 100).build_and_execute(|| {
+		MaxDelegatorsPerValidator::set(2);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 30));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(12), 1, 20));
+		Author::set(1);
+		run_to_block(2);
+
+		// 11 shrinks below 12, but keeps its spot.
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(11), 29));
+		assert_eq!(CountedDelegators::<Test>::get(1).to_vec(), vec![(10, 50), (11, 1)]);
+		dpos_events();
+
+		let count = |validator, delegator| {
+			Dpos::count_delegator(RuntimeOrigin::signed(3), validator, delegator)
+		};
+		assert_noop!(count(1, 3), Error::<Test>::NotDelegator);
+		assert_noop!(count(2, 12), Error::<Test>::NotNominated);
+		assert_noop!(count(1, 10), Error::<Test>::AlreadyCounted);
+
+		// Anyone can swap 12 in for free.
+		assert_eq!(count(1, 12).unwrap().pays_fee, Pays::No);
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Oversubscribed { delegator: 11, validator: 1 },
+				Event::DelegatorCounted { reporter: 3, delegator: 12, validator: 1 },
+			]
+		);
+		assert_eq!(CountedDelegators::<Test>::get(1).to_vec(), vec![(10, 50), (12, 20)]);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 90);
+		assert_eq!(Dpos::oversubscribed(&11), vec![1]);
+		// It only earns from now on.
+		assert_eq!(Dpos::pending_rewards(&12), 0);
+		assert_noop!(count(1, 11), Error::<Test>::NotEnoughToCount);
+
+		// A spot that frees up can be filled by anyone.
+		assert_ok!(Dpos::undelegate(RuntimeOrigin::signed(10)));
+		assert_ok!(count(1, 11));
+		assert_eq!(CountedDelegators::<Test>::get(1).to_vec(), vec![(12, 20), (11, 1)]);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 41);
+	});
+}
+
+#[test]
+fn restaking_can_prevent_an_eviction() {
+	StateBuilder::default().with_balance(12, 100).build_and_execute(|| {
+		MaxDelegatorsPerValidator::set(2);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 30));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(12), 1, 10));
+		assert_ok!(Dpos::set_payee(RuntimeOrigin::signed(11), RewardDestination::Staked));
+		author_every_slot(1);
+		run_to_block(2);
+		dpos_events();
+
+		// The rewards of 11 are restaked before it would be evicted, leaving no room for 12.
+		assert_ok!(Dpos::increase_delegation(RuntimeOrigin::signed(12), 25));
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Rewarded { who: 11, validator: 1, amount: 30 },
+				Event::Oversubscribed { delegator: 12, validator: 1 },
+				Event::Delegated { delegator: 12, amount: 25 },
+			]
+		);
+		assert_eq!(CountedDelegators::<Test>::get(1).to_vec(), vec![(10, 50), (11, 60)]);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 130);
+	});
+}
+
+#[test]
+fn unregister_validator_works() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_noop!(
+			Dpos::unregister_validator(RuntimeOrigin::signed(1)),
+			Error::<Test>::NotValidator
+		);
+
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 40, Perbill::zero()));
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(1)));
+
+		assert!(Validators::<Test>::get(1).is_none());
+		assert!(candidate_list().is_empty());
+		// The self stake is unbonding, but still held.
+		assert_eq!(held(HoldReason::ValidatorBond, 1), 40);
+		assert_eq!(
+			Unlocking::<Test>::get(1).to_vec(),
+			vec![UnlockChunk { validator: 1, value: 40, unlock_epoch: 2 }]
+		);
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::ValidatorRegistered { who: 1, self_stake: 40, commission: Perbill::zero() },
+				Event::ValidatorUnregistered { who: 1, self_stake: 40 },
+			]
+		);
+	});
+}
+
+#[test]
+fn top_validators_stay_sorted() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 30, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(3), 20, Perbill::zero()));
+
+		let order = candidate_list().into_iter().map(|(who, _)| who).collect::<Vec<_>>();
+		assert_eq!(order, vec![2, 3, 1]);
+
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(3)));
+		let order = candidate_list().into_iter().map(|(who, _)| who).collect::<Vec<_>>();
+		assert_eq!(order, vec![2, 1]);
+	});
+}
+
+#[test]
+fn delegate_works() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+
+		assert_eq!(Delegations::<Test>::get(10), Some(single_delegation(1, 30)));
+		assert_eq!(held(HoldReason::Delegation, 10), 30);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 40);
+		assert_eq!(candidate_list(), vec![(1, 40)]);
+
+		assert_ok!(Dpos::increase_delegation(RuntimeOrigin::signed(10), 20));
+		assert_eq!(Delegations::<Test>::get(10), Some(single_delegation(1, 50)));
+		assert_eq!(held(HoldReason::Delegation, 10), 50);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 60);
+
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::ValidatorRegistered { who: 1, self_stake: 10, commission: Perbill::zero() },
+				Event::Delegated { delegator: 10, amount: 30 },
+				Event::Nominated { delegator: 10, targets: vec![1] },
+				Event::Delegated { delegator: 10, amount: 20 },
+			]
+		);
+	});
+}
+
+#[test]
+fn delegate_fails() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+
+		// Can only delegate to candidates.
+		assert_noop!(Dpos::delegate(RuntimeOrigin::signed(10), 2, 30), Error::<Test>::NotValidator);
+		// Validators cannot delegate.
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 10, Perbill::zero()));
+		assert_noop!(
+			Dpos::delegate(RuntimeOrigin::signed(2), 1, 30),
+			Error::<Test>::AlreadyValidator
+		);
+		assert_noop!(
+			Dpos::delegate(RuntimeOrigin::signed(10), 1, 0),
+			Error::<Test>::InsufficientDelegation
+		);
+		assert_noop!(
+			Dpos::delegate(RuntimeOrigin::signed(10), 1, 1000),
+			TokenError::FundsUnavailable
+		);
+		assert_noop!(
+			Dpos::increase_delegation(RuntimeOrigin::signed(10), 10),
+			Error::<Test>::NotDelegator
+		);
+
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+		assert_noop!(
+			Dpos::delegate(RuntimeOrigin::signed(10), 2, 30),
+			Error::<Test>::AlreadyDelegator
+		);
+		// Delegators cannot register.
+		assert_noop!(
+			Dpos::register_validator(RuntimeOrigin::signed(10), 10, Perbill::zero()),
+			Error::<Test>::AlreadyDelegator
+		);
+	});
+}
+
+#[test]
+fn decrease_and_undelegate_works() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+
+		assert_noop!(
+			Dpos::decrease_delegation(RuntimeOrigin::signed(10), 31),
+			Error::<Test>::NotEnoughDelegated
+		);
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(10), 10));
+		assert_eq!(Delegations::<Test>::get(10), Some(single_delegation(1, 20)));
+		assert_eq!(held(HoldReason::Delegation, 10), 30);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 30);
+
+		assert_ok!(Dpos::undelegate(RuntimeOrigin::signed(10)));
+		assert!(Delegations::<Test>::get(10).is_none());
+		assert_eq!(held(HoldReason::Delegation, 10), 30);
+		assert_eq!(
+			Unlocking::<Test>::get(10).to_vec(),
+			vec![UnlockChunk { validator: 1, value: 30, unlock_epoch: 2 }]
+		);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 10);
+		assert_eq!(candidate_list(), vec![(1, 10)]);
+
+		assert_noop!(Dpos::undelegate(RuntimeOrigin::signed(10)), Error::<Test>::NotDelegator);
+	});
+}
+
+#[test]
+fn nominate_works() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+
+		assert_noop!(
+			Dpos::nominate(RuntimeOrigin::signed(10), vec![3]),
+			Error::<Test>::NotValidator
+		);
+		assert_ok!(Dpos::nominate(RuntimeOrigin::signed(10), vec![2]));
+
+		assert_eq!(Delegations::<Test>::get(10), Some(single_delegation(2, 30)));
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 10);
+		assert_eq!(Validators::<Test>::get(2).unwrap().total_backing, 50);
+		let order = candidate_list().into_iter().map(|(who, _)| who).collect::<Vec<_>>();
+		assert_eq!(order, vec![2, 1]);
+		System::assert_last_event(Event::Nominated { delegator: 10, targets: vec![2] }.into());
+	});
+}
+
+#[test]
+fn delegations_survive_validator_unregistering() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(1)));
+
+		// The record sticks around with only the delegated backing, but is not a candidate.
+		assert_eq!(
+			Validators::<Test>::get(1),
+			Some(ValidatorInfo { self_stake: 0, total_backing: 30, ..Default::default() })
+		);
+		assert!(candidate_list().is_empty());
+		assert_noop!(
+			Dpos::increase_delegation(RuntimeOrigin::signed(10), 10),
+			Error::<Test>::NotValidator
+		);
+
+		// Registering again picks the delegation back up.
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 5, Perbill::zero()));
+		assert_eq!(
+			Validators::<Test>::get(1),
+			Some(ValidatorInfo { self_stake: 5, total_backing: 35, ..Default::default() })
+		);
+
+		// Once everyone left, the record is gone.
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(1)));
+		assert_ok!(Dpos::undelegate(RuntimeOrigin::signed(10)));
+		assert!(Validators::<Test>::get(1).is_none());
+	});
+}
+
+#[test]
+fn election_happens_every_epoch() {
+	StateBuilder::default().build_and_execute(|| {
+		MaxValidators::set(2);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(3), 30, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+		dpos_events();
+
+		// Nothing happens before the epoch boundary.
+		run_to_block(99);
+		assert_eq!(CurrentEpoch::<Test>::get(), 0);
+		assert!(ActiveValidators::<Test>::get().is_empty());
+
+		run_to_block(100);
+		assert_eq!(CurrentEpoch::<Test>::get(), 1);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![1, 3]);
+		assert_eq!(ReportedValidators::get(), vec![1, 3]);
+		assert_eq!(dpos_events(), vec![Event::NewEpoch { epoch: 1, validators: vec![1, 3] }]);
+
+		// Backing changes are picked up at the next epoch.
+		assert_ok!(Dpos::nominate(RuntimeOrigin::signed(10), vec![2]));
+		run_to_block(200);
+		assert_eq!(CurrentEpoch::<Test>::get(), 2);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![2, 3]);
+		assert_eq!(ReportedValidators::get(), vec![2, 3]);
+	});
+}
+
+#[test]
+fn empty_election_keeps_previous_set() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		run_to_block(100);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![1]);
+
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(1)));
+		dpos_events();
+		run_to_block(200);
+		assert_eq!(CurrentEpoch::<Test>::get(), 2);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![1]);
+		assert_eq!(dpos_events(), vec![Event::EmptyElection { epoch: 2 }]);
+	});
+}
+
+#[test]
+fn election_weight_is_accounted() {
+	StateBuilder::default().build_and_execute(|| {
+		use frame_support::{traits::Hooks, weights::constants::RocksDbWeight};
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		// Without active validators, the author is not the one of the slot, so it is not rewarded
+		// past reading the schedule, and there is no slot to note either, past finding the author
+		// and the empty schedule.
+		let reward_weight = RocksDbWeight::get().reads(3 + 2);
+		assert_eq!(Dpos::on_initialize(87), reward_weight);
+		// Going through the candidate list reads all 12 bags, and the node and registration of the
+		// only candidate.
+		let list_reads = 12 + 2;
+		// The first page of the snapshot also reads the record of the candidate.
+		assert_eq!(
+			Dpos::on_initialize(88),
+			reward_weight + RocksDbWeight::get().reads_writes(3 + list_reads, 3)
+		);
+		// Without delegations, the snapshot is done after the first page.
+		assert_eq!(Dpos::on_initialize(89), reward_weight + RocksDbWeight::get().reads(1));
+		assert_eq!(Dpos::on_initialize(99), reward_weight);
+		// Without inflation, the epoch payout only reads what it is computed from. Without slot
+		// records, checking for offences only reads the epoch, and applying deferred slashes reads
+		// it again to take the slashes of the next one. Without a solution, the election falls
+		// back to the top of the candidate list, then reads the backing of the winner and the
+		// randomness for the slot schedule. Last, the exposure of the winner is taken.
+		assert_eq!(
+			Dpos::on_initialize(100),
+			reward_weight +
+				RocksDbWeight::get().reads_writes(3 + 1 + 2 + 3 + list_reads + 2 + 2, 1 + 8 + 1)
+		);
+	});
+}
+
+#[test]
+fn slots_are_tracked_per_validator() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		run_to_block(100);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![2, 1]);
+
+		// Slots alternate between 1 and 2, but only 2 authors blocks.
+		Author::set(2);
+		run_to_block(110);
+		assert_eq!(SlotsOf::<Test>::get(1), SlotRecord { expected: 5, authored: 0 });
+		assert_eq!(SlotsOf::<Test>::get(2), SlotRecord { expected: 5, authored: 5 });
+
+		// Without offences, the records are reset at the end of the epoch.
+		run_to_block(200);
+		assert_eq!(SlotsOf::<Test>::iter().count(), 0);
+		assert!(Chilled::<Test>::get(1).is_none());
+	});
+}
+
+#[test]
+fn offline_validators_are_chilled_and_slashed() {
+	StateBuilder::default().build_and_execute(|| {
+		miss_slots_until_epoch_end();
+		run_to_block(200);
+		let slash = Perbill::from_percent(6);
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Rewarded { who: 2, validator: 2, amount: 100 },
+				Event::Offence { who: 1, epoch: 1, expected: 50, authored: 20, slash },
+				Event::Chilled { who: 1 },
+				// Rewards earned before the offence are paid out first.
+				Event::Rewarded { who: 10, validator: 1, amount: 1200 },
+				Event::Slashed { who: 1, amount: 1 },
+				Event::Slashed { who: 10, amount: 1 },
+				Event::NewEpoch { epoch: 2, validators: vec![2] },
+			]
+		);
+		assert_eq!(Chilled::<Test>::get(1), Some(1));
+		assert_eq!(candidate_list(), vec![(2, 60)]);
+		// The bond and the delegation are kept.
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 50 - 2);
+
+		// The validator is back in elections once it registers again.
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(1)));
+		assert!(Chilled::<Test>::get(1).is_none());
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_eq!(candidate_list(), vec![(2, 60), (1, 29 + 20)]);
+	});
+}
+
+#[test]
+fn offenders_that_unregistered_are_only_slashed() {
+	StateBuilder::default().build_and_execute(|| {
+		MissedSlotsThreshold::set(Perbill::from_percent(50));
+		MissedSlotsSlash::set(Perbill::from_percent(10));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 60, Perbill::zero()));
+		run_to_block(100);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![2, 1]);
+
+		// 1 leaves, but stays active until the end of the epoch, missing all of its slots.
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(1)));
+		Author::set(2);
+		run_to_block(199);
+		dpos_events();
+		run_to_block(200);
+		let events = dpos_events();
+		let slash = Perbill::from_percent(10);
+		assert!(events.contains(&Event::Offence {
+			who: 1,
+			epoch: 1,
+			expected: 50,
+			authored: 0,
+			slash
+		}));
+		assert!(!events.contains(&Event::Chilled { who: 1 }));
+		assert!(events.contains(&Event::Slashed { who: 1, amount: 2 }));
+		assert!(Chilled::<Test>::get(1).is_none());
+		assert!(Validators::<Test>::get(1).is_none());
+
+		// Registering again makes it a candidate like any other.
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_eq!(Dpos::status(&1), Some(ValidatorStatus::Validating));
+		assert_eq!(candidate_list(), vec![(2, 60), (1, 20)]);
+	});
+}
+
+#[test]
+fn registering_clears_a_stale_chill() {
+	StateBuilder::default().build_and_execute(|| {
+		// Left behind by offenders chilled after unregistering, before they were only slashed.
+		Chilled::<Test>::insert(1, 1);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_eq!(Dpos::status(&1), Some(ValidatorStatus::Validating));
+		assert_eq!(candidate_list(), vec![(1, 20)]);
+	});
+}
+
+#[test]
+fn validators_can_chill() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+		run_to_block(100);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![1, 2]);
+
+		assert_noop!(Dpos::chill(RuntimeOrigin::signed(3)), Error::<Test>::NotValidator);
+		assert_noop!(Dpos::validate(RuntimeOrigin::signed(1)), Error::<Test>::NotChilled);
+		dpos_events();
+		assert_ok!(Dpos::chill(RuntimeOrigin::signed(1)));
+		assert_eq!(dpos_events(), vec![Event::Chilled { who: 1 }]);
+		assert_noop!(Dpos::chill(RuntimeOrigin::signed(1)), Error::<Test>::AlreadyChilled);
+		assert_eq!(Dpos::status(&1), Some(ValidatorStatus::Chilled));
+		assert_eq!(Dpos::status(&2), Some(ValidatorStatus::Validating));
+		assert_eq!(Dpos::status(&3), None);
+
+		// The validator finishes the epoch, then is left out of elections, keeping its bond and
+		// its delegations.
+		assert_eq!(candidate_list(), vec![(2, 20)]);
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 5));
+		run_to_block(200);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![2]);
+		assert_eq!(held(HoldReason::ValidatorBond, 1), 10);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 10 + 30 + 5);
+
+		assert_ok!(Dpos::validate(RuntimeOrigin::signed(1)));
+		assert_eq!(dpos_events().last(), Some(&Event::Validating { who: 1 }));
+		assert_eq!(Dpos::status(&1), Some(ValidatorStatus::Validating));
+		assert_eq!(candidate_list(), vec![(1, 45), (2, 20)]);
+		run_to_block(300);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![1, 2]);
+	});
+}
+
+#[test]
+fn offenders_can_validate_again() {
+	StateBuilder::default().build_and_execute(|| {
+		miss_slots_until_epoch_end();
+		run_to_block(200);
+		assert_eq!(Dpos::status(&1), Some(ValidatorStatus::Chilled));
+
+		assert_ok!(Dpos::validate(RuntimeOrigin::signed(1)));
+		assert!(Chilled::<Test>::get(1).is_none());
+		assert_eq!(candidate_list(), vec![(2, 60), (1, 48)]);
+	});
+}
+
+#[test]
+fn slashes_can_be_deferred() {
+	StateBuilder::default().build_and_execute(|| {
+		SlashDeferDuration::set(1);
+		miss_slots_until_epoch_end();
+		run_to_block(200);
+		let fraction = Perbill::from_percent(6);
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Rewarded { who: 2, validator: 2, amount: 100 },
+				Event::Offence { who: 1, epoch: 1, expected: 50, authored: 20, slash: fraction },
+				Event::Chilled { who: 1 },
+				Event::SlashDeferred { who: 1, fraction, epoch: 3 },
+				Event::NewEpoch { epoch: 2, validators: vec![2] },
+			]
+		);
+		assert_eq!(
+			UnappliedSlashes::<Test>::get(3).to_vec(),
+			vec![UnappliedSlash { validator: 1, fraction, epoch: 1 }]
+		);
+
+		// The slash waits for a full epoch.
+		run_to_block(299);
+		assert_eq!(slashed_events(), vec![]);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 50);
+
+		run_to_block(300);
+		assert_eq!(
+			slashed_events(),
+			vec![Event::Slashed { who: 1, amount: 1 }, Event::Slashed { who: 10, amount: 1 }]
+		);
+		assert!(!UnappliedSlashes::<Test>::contains_key(3));
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 50 - 2);
+	});
+}
+
+#[test]
+fn deferred_slashes_can_be_cancelled() {
+	StateBuilder::default().build_and_execute(|| {
+		SlashDeferDuration::set(1);
+		miss_slots_until_epoch_end();
+		run_to_block(200);
+
+		assert_noop!(
+			Dpos::cancel_deferred_slash(RuntimeOrigin::signed(2), 3, 1),
+			sp_runtime::DispatchError::BadOrigin
+		);
+		assert_noop!(
+			Dpos::cancel_deferred_slash(RuntimeOrigin::root(), 2, 1),
+			Error::<Test>::NoDeferredSlash
+		);
+		assert_noop!(
+			Dpos::cancel_deferred_slash(RuntimeOrigin::root(), 3, 2),
+			Error::<Test>::NoDeferredSlash
+		);
+		dpos_events();
+		assert_ok!(Dpos::cancel_deferred_slash(RuntimeOrigin::root(), 3, 1));
+		assert_eq!(dpos_events(), vec![Event::SlashCancelled { who: 1, epoch: 3 }]);
+		assert!(!UnappliedSlashes::<Test>::contains_key(3));
+
+		// Nothing is slashed, but the validator stays chilled.
+		run_to_block(300);
+		assert_eq!(slashed_events(), vec![]);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 50);
+		assert_eq!(Chilled::<Test>::get(1), Some(1));
+	});
+}
+
+#[test]
+fn deferred_slashes_use_the_exposure_of_the_offence() {
+	StateBuilder::default().build_and_execute(|| {
+		SlashDeferDuration::set(1);
+		miss_slots_until_epoch_end();
+		run_to_block(200);
+
+		// Delegating after the offence does not put the new stake at risk, and stake unbonding
+		// since is slashed all the same.
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 50));
+		assert_ok!(Dpos::undelegate(RuntimeOrigin::signed(10)));
+		run_to_block(300);
+		assert_eq!(
+			slashed_events(),
+			vec![Event::Slashed { who: 1, amount: 1 }, Event::Slashed { who: 10, amount: 1 }]
+		);
+		assert_eq!(held(HoldReason::Delegation, 11), 50);
+		assert_eq!(held(HoldReason::Delegation, 10), 30 - 1);
+		assert_eq!(Unlocking::<Test>::get(10)[0].value, 30 - 1);
+	});
+}
+
+#[test]
+fn sessions_pull_the_elected_set() {
+	use pallet_session::SessionManager;
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		// Before the first election, the genesis validators of the session pallet are kept.
+		assert_eq!(<Dpos as SessionManager<_>>::new_session(1), None);
+
+		run_to_block(100);
+		assert_eq!(<Dpos as SessionManager<_>>::new_session(2), Some(vec![2, 1]));
+	});
+}
+
+#[test]
+fn electing_ahead_follows_the_session_queue() {
+	use pallet_session::SessionManager;
+	StateBuilder::default().build_and_execute(|| {
+		ElectAhead::set(true);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+
+		// The winners are queued along with the session pallet, which still runs with its genesis
+		// validators, so there is no slot schedule yet.
+		run_to_block(100);
+		assert_eq!(QueuedValidators::<Test>::get().to_vec(), vec![2, 1]);
+		assert!(ActiveValidators::<Test>::get().is_empty());
+		assert_eq!(Dpos::slot_author(100), None);
+		assert_eq!(<Dpos as SessionManager<_>>::new_session(2), Some(vec![2, 1]));
+		assert!(
+			dpos_events().contains(&Event::ValidatorsQueued { epoch: 2, validators: vec![2, 1] })
+		);
+
+		// They become active with the session they were queued for, as the next set is queued.
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(3), 30, Perbill::zero()));
+		run_to_block(200);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![2, 1]);
+		assert_eq!(QueuedValidators::<Test>::get().to_vec(), vec![3, 2, 1]);
+		assert_eq!(ReportedValidators::get(), vec![2, 1]);
+		assert!(Exposures::<Test>::get(2, 2).is_some());
+		assert!(Exposures::<Test>::get(2, 3).is_none());
+		assert_eq!(<Dpos as SessionManager<_>>::new_session(3), Some(vec![3, 2, 1]));
+	});
+}
+
+#[test]
+fn only_registered_candidates_are_elected() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		Unregistered::set(vec![2]);
+		run_to_block(100);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![1]);
+
+		// Nor are the winners of a solution that dropped their registration since the snapshot.
+		Unregistered::set(vec![]);
+		run_to_block(190);
+		let solution = Dpos::mine_solution(&Dpos::snapshot().unwrap()).unwrap();
+		assert_ok!(Dpos::submit_solution(RuntimeOrigin::none(), solution));
+		Unregistered::set(vec![1]);
+		run_to_block(200);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![2]);
+	});
+}
+
+#[test]
+fn session_keys_are_tracked() {
+	use frame_support::traits::OneSessionHandler;
+	use session::AuthorityId;
+	StateBuilder::default().build_and_execute(|| {
+		let key = |byte| AuthorityId::from(sp_core::sr25519::Public::from_raw([byte; 32]));
+		let validators = [(1, key(1)), (2, key(2))];
+		Dpos::on_genesis_session(validators.iter().map(|(who, k)| (who, k.clone())));
+		assert_eq!(Authorities::<Test>::get().to_vec(), vec![key(1), key(2)]);
+
+		// The keys are only replaced when the set changes.
+		let validators = [(3, key(3))];
+		let keys = || validators.iter().map(|(who, k)| (who, k.clone()));
+		Dpos::on_new_session(false, keys(), keys());
+		assert_eq!(Authorities::<Test>::get().to_vec(), vec![key(1), key(2)]);
+		Dpos::on_new_session(true, keys(), keys());
+		assert_eq!(Authorities::<Test>::get().to_vec(), vec![key(3)]);
+	});
+}
+
+#[test]
+fn slots_go_round_robin() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_eq!(Dpos::slot_author(1), None);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(3), 30, Perbill::zero()));
+		run_to_block(100);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![3, 2, 1]);
+		assert_eq!(ActiveBacking::<Test>::get().to_vec(), vec![30, 20, 10]);
+
+		let authors = (100..106).map(|n| Dpos::slot_author(n).unwrap()).collect::<Vec<_>>();
+		assert_eq!(authors, vec![2, 1, 3, 2, 1, 3]);
+	});
+}
+
+#[test]
+fn authors_are_found_in_the_digest() {
+	use codec::Encode;
+	use sp_runtime::{ConsensusEngineId, DigestItem};
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		run_to_block(100);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![2, 1]);
+
+		let find = |digests: &[(ConsensusEngineId, Vec<u8>)]| {
+			DigestAuthor::<Test>::find_author(digests.iter().map(|(id, data)| (*id, &data[..])))
+		};
+		assert_eq!(find(&[]), None);
+		assert_eq!(find(&[(*b"aura", 0u32.encode())]), None);
+		// The index must be of an active validator.
+		assert_eq!(find(&[(DPOS_ENGINE_ID, 2u32.encode())]), None);
+		assert_eq!(find(&[(DPOS_ENGINE_ID, vec![1])]), None);
+		assert_eq!(find(&[(*b"aura", 0u32.encode()), (DPOS_ENGINE_ID, 1u32.encode())]), Some(1));
+
+		// The pallet looks the author up in the pre-runtime digests of the block.
+		System::deposit_log(DigestItem::PreRuntime(DPOS_ENGINE_ID, 1u32.encode()));
+		assert_eq!(Dpos::find_author(), Some(1));
+
+		// Without a digest, the author can be taken from the slot schedule instead.
+		assert_eq!(SlotAuthor::<Test>::find_author(Vec::new()), Dpos::slot_author(100));
+	});
+}
+
+#[test]
+fn only_the_author_of_the_slot_is_rewarded() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		run_to_block(100);
+		assert_eq!(ActiveValidators::<Test>::get().to_vec(), vec![2, 1]);
+		dpos_events();
+
+		// The slot of block 101 is 1's, so 2 claiming it gets nothing.
+		assert_eq!(Dpos::slot_author(101), Some(1));
+		Author::set(2);
+		run_to_block(101);
+		assert!(dpos_events().is_empty());
+		assert_eq!(SlotsOf::<Test>::get(1), SlotRecord { expected: 1, authored: 0 });
+
+		run_to_block(102);
+		assert_eq!(dpos_events(), vec![Event::Rewarded { who: 2, validator: 2, amount: 100 }]);
+	});
+}
+
+#[test]
+fn ideal_staking_curve_peaks_at_the_ideal_stake() {
+	use payout::{EraPayout, IdealStakingCurve};
+	type Curve = IdealStakingCurve<MinInflation, MaxInflation, IdealStake, Falloff, EpochsPerYear>;
+
+	MinInflation::set(Perbill::from_percent(2));
+	MaxInflation::set(Perbill::from_percent(10));
+	let inflation = |staked| Curve::inflation(Perbill::from_percent(staked));
+	assert_eq!(inflation(0), Perbill::from_percent(2));
+	assert_eq!(inflation(25), Perbill::from_percent(6));
+	assert_eq!(inflation(50), Perbill::from_percent(10));
+	// Past the ideal stake, it falls back to the minimum over `Falloff`.
+	assert_eq!(inflation(60), Perbill::from_rational(68u32, 1000));
+	assert_eq!(inflation(75), Perbill::from_percent(2));
+	assert_eq!(inflation(100), Perbill::from_percent(2));
+
+	// The remainder always brings the payout up to the maximum inflation.
+	assert_eq!(<Curve as EraPayout<Balance>>::era_payout(500, 1000), (100, 0));
+	assert_eq!(<Curve as EraPayout<Balance>>::era_payout(250, 1000), (60, 40));
+	assert_eq!(<Curve as EraPayout<Balance>>::era_payout(0, 0), (0, 0));
+	EpochsPerYear::set(10);
+	assert_eq!(<Curve as EraPayout<Balance>>::era_payout(250, 1000), (6, 4));
+}
+
+#[test]
+fn epoch_payout_is_shared_by_authored_blocks() {
+	StateBuilder::default().build_and_execute(|| {
+		BlockReward::set(0);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 60, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 20));
+		run_to_block(100);
+		assert_eq!(ActiveBacking::<Test>::get().to_vec(), vec![60, 40]);
+
+		MinInflation::set(Perbill::from_percent(2));
+		MaxInflation::set(Perbill::from_percent(10));
+		// Validator 1 misses a single slot.
+		for n in 101..=200 {
+			Author::set(if n == 101 { 7 } else { Dpos::slot_author(n).unwrap() });
+			run_to_block(n);
+		}
+
+		// 20% of the issuance of 500 is staked, for an inflation of 5.2%. The 26 are shared 49/50
+		// by the validators, and the remainder of the 10% goes to the treasury.
+		let events = dpos_events()
+			.into_iter()
+			.filter(|e| matches!(e, Event::Rewarded { .. } | Event::EpochPaid { .. }))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			events,
+			vec![Event::EpochPaid { epoch: 1, validator_payout: 26, remainder: 24 }]
+		);
+		assert_eq!(EpochRewards::<Test>::get(1, 1), Some(13));
+		assert_eq!(EpochRewards::<Test>::get(1, 2), Some(13));
+		assert_eq!(<Test as Config>::NativeBalance::balance(&TreasuryAccount::get()), 24);
+
+		// Anyone can pay them out, and the delegators get their share right away.
+		assert_ok!(Dpos::payout_stakers(RuntimeOrigin::signed(3), 1, 1));
+		assert_ok!(Dpos::payout_stakers(RuntimeOrigin::signed(3), 2, 1));
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Rewarded { who: 1, validator: 1, amount: 6 },
+				Event::Rewarded { who: 10, validator: 1, amount: 7 },
+				Event::Rewarded { who: 2, validator: 2, amount: 13 },
+			]
+		);
+		assert_eq!(Dpos::pending_rewards(&10), 0);
+	});
+}
+
+#[test]
+fn exposures_are_kept_for_the_history_depth() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(
+			RuntimeOrigin::signed(1),
+			20,
+			Perbill::from_percent(5)
+		));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+		run_to_block(100);
+		let exposure = Exposure::<Test> {
+			own: 20,
+			total: 50,
+			commission: Perbill::from_percent(5),
+			others: BoundedVec::truncate_from(vec![(10, 30)]),
+		};
+		assert_eq!(Exposures::<Test>::get(1, 1), Some(exposure));
+
+		// Later changes only show in the exposures of later epochs.
+		assert_ok!(Dpos::increase_delegation(RuntimeOrigin::signed(10), 10));
+		assert_eq!(Exposures::<Test>::get(1, 1).unwrap().total, 50);
+		run_to_block(200);
+		assert_eq!(Exposures::<Test>::get(2, 1).unwrap().total, 60);
+
+		// Epoch 1 is forgotten once epoch 5 starts.
+		run_to_block(499);
+		assert!(Exposures::<Test>::contains_key(1, 1));
+		run_to_block(500);
+		assert!(!Exposures::<Test>::contains_key(1, 1));
+		assert_eq!(Exposures::<Test>::iter_keys().count(), 4);
+	});
+}
+
+#[test]
+fn payouts_use_the_exposure_of_their_epoch() {
+	StateBuilder::default().build_and_execute(|| {
+		BlockReward::set(0);
+		MinInflation::set(Perbill::from_percent(10));
+		MaxInflation::set(Perbill::from_percent(10));
+		Author::set(1);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+		run_to_block(100);
+
+		// A delegator joining mid-epoch has no share of its payout.
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 50));
+		run_to_block(200);
+		assert_eq!(EpochRewards::<Test>::get(1, 1), Some(50));
+		dpos_events();
+
+		assert_ok!(Dpos::payout_stakers(RuntimeOrigin::signed(3), 1, 1));
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Rewarded { who: 1, validator: 1, amount: 20 },
+				Event::Rewarded { who: 10, validator: 1, amount: 30 },
+			]
+		);
+		assert_noop!(
+			Dpos::payout_stakers(RuntimeOrigin::signed(3), 1, 1),
+			Error::<Test>::NothingToPayOut
+		);
+		assert_noop!(
+			Dpos::payout_stakers(RuntimeOrigin::signed(3), 2, 1),
+			Error::<Test>::NothingToPayOut
+		);
+
+		// Payouts left unclaimed for longer than the history depth are forfeited.
+		// Epoch 1 minted another 10% of the issuance.
+		run_to_block(300);
+		assert_eq!(EpochRewards::<Test>::get(2, 1), Some(55));
+		run_to_block(600);
+		assert_noop!(
+			Dpos::payout_stakers(RuntimeOrigin::signed(3), 1, 2),
+			Error::<Test>::NothingToPayOut
+		);
+	});
+}
+
+#[test]
+fn stake_weighted_slots_favour_backing() {
+	StateBuilder::default().build_and_execute(|| {
+		StakeWeightedSlots::set(true);
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 90, Perbill::zero()));
+		run_to_block(100);
+		let seed = SlotSeed::<Test>::get();
+		assert_ne!(seed, Default::default());
+
+		let authors = (100..1100).map(|n| Dpos::slot_author(n).unwrap()).collect::<Vec<_>>();
+		let by_2 = authors.iter().filter(|who| **who == 2).count();
+		assert!((850..950).contains(&by_2), "validator 2 authored {by_2} slots");
+
+		// The schedule is fixed for the epoch.
+		assert_eq!(authors, (100..1100).map(|n| Dpos::slot_author(n).unwrap()).collect::<Vec<_>>());
+
+		// A new epoch draws a new seed, and so a new schedule.
+		RandomSeed::set(H256::repeat_byte(7));
+		run_to_block(200);
+		assert_ne!(SlotSeed::<Test>::get(), seed);
+		assert_ne!(authors, (100..1100).map(|n| Dpos::slot_author(n).unwrap()).collect::<Vec<_>>());
+	});
+}
+
+#[test]
+fn author_is_rewarded_right_away() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		dpos_events();
+
+		author_every_slot(1);
+		run_to_block(2);
+
+		// Without delegators, the author gets everything.
+		assert_eq!(dpos_events(), vec![Event::Rewarded { who: 1, validator: 1, amount: 100 }]);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&1), 80 + 100);
+		assert_eq!(Validators::<Test>::get(1).unwrap().reward_per_share, FixedU128::zero());
+	});
+}
+
+#[test]
+fn delegators_claim_their_rewards() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 30));
+		dpos_events();
+
+		author_every_slot(1);
+		run_to_block(3);
+
+		// 100 per block is split 20/50/30 according to stake, but only the author is paid.
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Rewarded { who: 1, validator: 1, amount: 20 },
+				Event::Rewarded { who: 1, validator: 1, amount: 20 },
+			]
+		);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&1), 80 + 40);
+		assert_eq!(Validators::<Test>::get(1).unwrap().reward_per_share, FixedU128::from(2));
+		assert_eq!(Dpos::pending_rewards(&10), 100);
+		assert_eq!(Dpos::pending_rewards(&11), 60);
+
+		assert_ok!(Dpos::claim_rewards(RuntimeOrigin::signed(10)));
+		assert_eq!(dpos_events(), vec![Event::Rewarded { who: 10, validator: 1, amount: 100 }]);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&10), 50 + 100);
+		assert_eq!(Dpos::pending_rewards(&10), 0);
+
+		// Claiming again pays nothing.
+		assert_ok!(Dpos::claim_rewards(RuntimeOrigin::signed(10)));
+		assert!(dpos_events().is_empty());
+		assert_noop!(Dpos::claim_rewards(RuntimeOrigin::signed(2)), Error::<Test>::NotDelegator);
+
+		// Only what is minted shows up in the issuance.
+		assert_eq!(<Test as Config>::NativeBalance::total_issuance(), 500 + 40 + 100);
+	});
+}
+
+#[test]
+fn rewards_go_to_the_payee() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 50, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+		assert_noop!(
+			Dpos::set_payee(RuntimeOrigin::signed(3), RewardDestination::Free),
+			Error::<Test>::NotStaker
+		);
+		assert_ok!(Dpos::set_payee(RuntimeOrigin::signed(1), RewardDestination::Account(3)));
+		assert_ok!(Dpos::set_payee(RuntimeOrigin::signed(10), RewardDestination::Account(11)));
+		assert_eq!(Payees::<Test>::get(1), RewardDestination::Account(3));
+		dpos_events();
+
+		author_every_slot(1);
+		run_to_block(2);
+		assert_ok!(Dpos::claim_rewards(RuntimeOrigin::signed(10)));
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Rewarded { who: 1, validator: 1, amount: 50 },
+				Event::Rewarded { who: 10, validator: 1, amount: 50 },
+			]
+		);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&1), 50);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&3), 100 + 50);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&10), 50);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&11), 100 + 50);
+
+		// Back to the free balance, which is the default.
+		assert_ok!(Dpos::set_payee(RuntimeOrigin::signed(10), RewardDestination::Free));
+		assert_eq!(dpos_events(), vec![Event::PayeeSet { who: 10, dest: RewardDestination::Free }]);
+		assert!(!Payees::<Test>::contains_key(10));
+
+		// The payee goes with the delegation.
+		assert_ok!(Dpos::set_payee(RuntimeOrigin::signed(10), RewardDestination::Staked));
+		assert_ok!(Dpos::undelegate(RuntimeOrigin::signed(10)));
+		assert!(!Payees::<Test>::contains_key(10));
+	});
+}
+
+#[test]
+fn rewards_can_be_restaked() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 30));
+		assert_ok!(Dpos::set_payee(RuntimeOrigin::signed(1), RewardDestination::Staked));
+		assert_ok!(Dpos::set_payee(RuntimeOrigin::signed(10), RewardDestination::Staked));
+
+		// The validator's share is added to its self stake right away.
+		author_every_slot(1);
+		run_to_block(2);
+		let info = Validators::<Test>::get(1).unwrap();
+		assert_eq!((info.self_stake, info.total_backing), (40, 120));
+		assert_eq!(held(HoldReason::ValidatorBond, 1), 40);
+		assert_eq!(candidate_list(), vec![(1, 120)]);
+
+		// The delegators' share once it is settled.
+		assert_ok!(Dpos::claim_rewards(RuntimeOrigin::signed(10)));
+		assert_ok!(Dpos::claim_rewards(RuntimeOrigin::signed(11)));
+		let delegation = Delegations::<Test>::get(10).unwrap();
+		assert_eq!((delegation.amount, delegation.nominations[0].stake), (100, 100));
+		assert_eq!(held(HoldReason::Delegation, 10), 100);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&10), 50);
+		assert_eq!(Delegations::<Test>::get(11).unwrap().amount, 30);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&11), 70 + 30);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 170);
+		assert_eq!(CountedDelegators::<Test>::get(1).to_vec(), vec![(10, 100), (11, 30)]);
+
+		// Rewards pending at a slash are restaked before it, and slashed with the rest.
+		run_to_block(3);
+		assert_eq!(Dpos::pending_rewards(&10), 58);
+		Dpos::slash(&1, Perbill::from_percent(50));
+		let delegation = Delegations::<Test>::get(10).unwrap();
+		assert_eq!(delegation.amount, 79);
+		assert_eq!(held(HoldReason::Delegation, 10), 79);
+	});
+}
+
+#[test]
+fn rewards_settle_on_stake_change() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 50, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 50, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+
+		author_every_slot(1);
+		run_to_block(2);
+		dpos_events();
+		assert_eq!(Dpos::pending_rewards(&10), 50);
+
+		// Topping up pays out what was accrued with the old amount.
+		assert_ok!(Dpos::increase_delegation(RuntimeOrigin::signed(10), 25));
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Rewarded { who: 10, validator: 1, amount: 50 },
+				Event::Delegated { delegator: 10, amount: 25 },
+			]
+		);
+		assert_eq!(Dpos::pending_rewards(&10), 0);
+
+		// 100 is now split 50/75.
+		run_to_block(3);
+		assert_eq!(Dpos::pending_rewards(&10), 60);
+
+		// Moving to another validator pays out as well, and starts from a clean slate there.
+		author_every_slot(2);
+		assert_ok!(Dpos::nominate(RuntimeOrigin::signed(10), vec![2]));
+		assert_eq!(<Test as Config>::NativeBalance::balance(&10), 25 + 50 + 60);
+		assert_eq!(Dpos::pending_rewards(&10), 0);
+		run_to_block(4);
+		assert_eq!(Dpos::pending_rewards(&10), 60);
+
+		// Leaving pays out too.
+		assert_ok!(Dpos::undelegate(RuntimeOrigin::signed(10)));
+		assert_eq!(<Test as Config>::NativeBalance::balance(&10), 25 + 50 + 60 + 60);
+	});
+}
+
+#[test]
+fn no_reward_for_unknown_author() {
+	StateBuilder::default().build_and_execute(|| {
+		let issuance = <Test as Config>::NativeBalance::total_issuance();
+		Author::set(42);
+		run_to_block(5);
+		assert!(dpos_events().is_empty());
+		assert_eq!(<Test as Config>::NativeBalance::total_issuance(), issuance);
+	});
+}
+
+#[test]
+fn commission_is_taken_before_sharing() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(
+			RuntimeOrigin::signed(1),
+			20,
+			Perbill::from_percent(50)
+		));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 80));
+		dpos_events();
+
+		author_every_slot(1);
+		run_to_block(2);
+
+		// 50 is commission, the other 50 is split 20/80.
+		assert_eq!(dpos_events(), vec![Event::Rewarded { who: 1, validator: 1, amount: 60 }]);
+		assert_eq!(Dpos::pending_rewards(&10), 40);
+	});
+}
+
+#[test]
+fn commission_change_is_bounded_per_epoch() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(
+			RuntimeOrigin::signed(1),
+			20,
+			Perbill::from_percent(20)
+		));
+		assert_noop!(
+			Dpos::set_commission(RuntimeOrigin::signed(2), Perbill::zero()),
+			Error::<Test>::NotValidator
+		);
+
+		// Moving within 10% of the commission at the start of the epoch is fine, in steps too.
+		assert_ok!(Dpos::set_commission(RuntimeOrigin::signed(1), Perbill::from_percent(25)));
+		assert_ok!(Dpos::set_commission(RuntimeOrigin::signed(1), Perbill::from_percent(30)));
+		assert_noop!(
+			Dpos::set_commission(RuntimeOrigin::signed(1), Perbill::from_percent(31)),
+			Error::<Test>::CommissionChangeTooLarge
+		);
+		assert_ok!(Dpos::set_commission(RuntimeOrigin::signed(1), Perbill::from_percent(10)));
+		assert_noop!(
+			Dpos::set_commission(RuntimeOrigin::signed(1), Perbill::from_percent(9)),
+			Error::<Test>::CommissionChangeTooLarge
+		);
+		System::assert_last_event(
+			Event::CommissionUpdated { who: 1, commission: Perbill::from_percent(10) }.into(),
+		);
+
+		// In the next epoch, the new baseline applies.
+		run_to_block(100);
+		assert_ok!(Dpos::set_commission(RuntimeOrigin::signed(1), Perbill::zero()));
+		assert_eq!(Validators::<Test>::get(1).unwrap().commission, Perbill::zero());
+	});
+}
+
+#[test]
+fn re_registering_does_not_bypass_commission_bound() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 80));
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(1)));
+
+		assert_noop!(
+			Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::one()),
+			Error::<Test>::CommissionChangeTooLarge
+		);
+		assert_ok!(Dpos::register_validator(
+			RuntimeOrigin::signed(1),
+			20,
+			Perbill::from_percent(10)
+		));
+
+		// Without delegations left over, any commission goes.
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::one()));
+	});
+}
+
+#[test]
+fn withdraw_unbonded_works() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(10), 10));
+
+		assert_noop!(
+			Dpos::withdraw_unbonded(RuntimeOrigin::signed(10)),
+			Error::<Test>::NothingToWithdraw
+		);
+
+		// One epoch in, unbond some more.
+		run_to_block(100);
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(10), 5));
+		assert_eq!(
+			Unlocking::<Test>::get(10).to_vec(),
+			vec![
+				UnlockChunk { validator: 1, value: 10, unlock_epoch: 2 },
+				UnlockChunk { validator: 1, value: 5, unlock_epoch: 3 },
+			]
+		);
+		assert_noop!(
+			Dpos::withdraw_unbonded(RuntimeOrigin::signed(10)),
+			Error::<Test>::NothingToWithdraw
+		);
+
+		// The first chunk matures.
+		run_to_block(200);
+		dpos_events();
+		assert_ok!(Dpos::withdraw_unbonded(RuntimeOrigin::signed(10)));
+		assert_eq!(dpos_events(), vec![Event::Withdrawn { who: 10, amount: 10 }]);
+		assert_eq!(held(HoldReason::Delegation, 10), 20);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&10), 80);
+		assert_eq!(
+			Unlocking::<Test>::get(10).to_vec(),
+			vec![UnlockChunk { validator: 1, value: 5, unlock_epoch: 3 }]
+		);
+
+		// And then the second one.
+		run_to_block(300);
+		assert_ok!(Dpos::withdraw_unbonded(RuntimeOrigin::signed(10)));
+		assert_eq!(held(HoldReason::Delegation, 10), 15);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&10), 85);
+		assert!(!Unlocking::<Test>::contains_key(10));
+	});
+}
+
+#[test]
+fn validator_withdraws_self_stake_after_unbonding() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 40, Perbill::zero()));
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(1)));
+		assert_eq!(<Test as Config>::NativeBalance::balance(&1), 60);
+
+		run_to_block(200);
+		assert_ok!(Dpos::withdraw_unbonded(RuntimeOrigin::signed(1)));
+		assert_eq!(held(HoldReason::ValidatorBond, 1), 0);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&1), 100);
+	});
+}
+
+#[test]
+fn unlocking_chunks_are_bounded() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+
+		// Unbonding twice in the same epoch shares a chunk.
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(10), 1));
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(10), 1));
+		assert_eq!(Unlocking::<Test>::get(10).len(), 1);
+
+		for epoch in 1..4 {
+			run_to_block(epoch * 100);
+			assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(10), 1));
+		}
+		assert_eq!(Unlocking::<Test>::get(10).len(), 4);
+
+		// Withdrawing first and the epoch after that is fine again.
+		run_to_block(400);
+		assert_noop!(
+			Dpos::decrease_delegation(RuntimeOrigin::signed(10), 1),
+			Error::<Test>::TooManyUnlockingChunks
+		);
+		assert_ok!(Dpos::withdraw_unbonded(RuntimeOrigin::signed(10)));
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(10), 1));
+	});
+}
+
+#[test]
+fn slash_is_proportional() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 40, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 40, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 20));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(3), 2, 20));
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(11), 10));
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(3), 10));
+		let issuance = <Test as Config>::NativeBalance::total_issuance();
+		dpos_events();
+
+		<Dpos as DoSlash<Test>>::do_slash(1, Perbill::from_percent(50));
+
+		assert_eq!(
+			dpos_events(),
+			vec![
+				Event::Slashed { who: 1, amount: 20 },
+				Event::Slashed { who: 10, amount: 15 },
+				Event::Slashed { who: 11, amount: 10 },
+			]
+		);
+		// `()` burns the slashed funds.
+		assert_eq!(<Test as Config>::NativeBalance::total_issuance(), issuance - 45);
+
+		assert_eq!(held(HoldReason::ValidatorBond, 1), 20);
+		assert_eq!(held(HoldReason::Delegation, 10), 15);
+		assert_eq!(held(HoldReason::Delegation, 11), 10);
+		assert_eq!(Delegations::<Test>::get(11).unwrap().amount, 5);
+		assert_eq!(
+			Unlocking::<Test>::get(11).to_vec(),
+			vec![UnlockChunk { validator: 1, value: 5, unlock_epoch: 2 }]
+		);
+		let info = Validators::<Test>::get(1).unwrap();
+		assert_eq!((info.self_stake, info.total_backing), (20, 40));
+		assert_eq!(candidate_list(), vec![(2, 50), (1, 40)]);
+
+		// Stake towards other validators is untouched, and not even visited.
+		assert_eq!(held(HoldReason::Delegation, 3), 20);
+		assert_eq!(Unlocking::<Test>::get(3).to_vec()[0].value, 10);
+		assert_eq!(UnbondingFrom::<Test>::iter_key_prefix(1).collect::<Vec<_>>(), vec![11]);
+	});
+}
+
+#[test]
+fn full_slash_removes_everything() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 40, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 30));
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(1)));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+
+		Dpos::slash(&1, Perbill::one());
+
+		assert!(!Validators::<Test>::contains_key(1));
+		assert!(!Delegations::<Test>::contains_key(10));
+		assert!(!DelegatorsOf::<Test>::contains_key(1, 10));
+		assert!(!Unlocking::<Test>::contains_key(1));
+		assert!(candidate_list().is_empty());
+		assert_eq!(held(HoldReason::ValidatorBond, 1), 0);
+		assert_eq!(held(HoldReason::Delegation, 10), 0);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&1), 50);
+		assert_eq!(<Test as Config>::NativeBalance::balance(&10), 70);
+	});
+}
+
+#[test]
+fn candidates_are_kept_in_bags() {
+	let mut builder = StateBuilder::default();
+	for who in 100..250 {
+		builder = builder.with_balance(who, 100);
+	}
+	builder.build_and_execute(|| {
+		// There is no cap on the number of candidates.
+		for who in 100..250 {
+			assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(who), 10, Perbill::zero()));
+		}
+		assert_eq!(candidate_list().len(), 150);
+
+		// Within a bag, candidates are in the order they came in.
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 15, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 12, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 2, 5));
+		assert_eq!(candidate_list()[..2], [(1, 15), (2, 17)]);
+		assert_eq!(CandidateNodes::<Test>::get(2).unwrap().bag_upper, 20);
+
+		// Anyone can fix up the order.
+		assert_noop!(
+			Dpos::put_in_front_of(RuntimeOrigin::signed(3), 1, 2),
+			Error::<Test>::NotHeavier
+		);
+		assert_noop!(
+			Dpos::put_in_front_of(RuntimeOrigin::signed(3), 2, 100),
+			Error::<Test>::NotInSameBag
+		);
+		assert_noop!(
+			Dpos::put_in_front_of(RuntimeOrigin::signed(3), 2, 3),
+			Error::<Test>::NotValidator
+		);
+		dpos_events();
+		let post_info = Dpos::put_in_front_of(RuntimeOrigin::signed(3), 2, 1).unwrap();
+		assert_eq!(post_info.pays_fee, Pays::No);
+		assert_eq!(dpos_events(), vec![Event::CandidateMoved { reporter: 3, who: 2, lighter: 1 }]);
+		assert_eq!(candidate_list()[..2], [(2, 17), (1, 15)]);
+
+		// Candidates move to another bag as their backing changes.
+		assert_ok!(Dpos::increase_delegation(RuntimeOrigin::signed(10), 10));
+		assert_eq!(CandidateNodes::<Test>::get(2).unwrap().bag_upper, 30);
+		assert_ok!(Dpos::nominate(RuntimeOrigin::signed(10), vec![1]));
+		assert_eq!(candidate_list()[..2], [(1, 30), (2, 12)]);
+
+		// And leave it when they unregister.
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(2)));
+		assert!(!CandidateList::<Test>::contains(&2));
+		assert_eq!(candidate_list().len(), 151);
+	});
+}
+
+#[test]
+fn delegation_chains_back_the_last_validator() {
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 20));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 10, 30));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(3), 11, 40));
+
+		assert_eq!(
+			Delegations::<Test>::get(3).map(|d| (d.targets.to_vec(), d.validators())),
+			Some((vec![11], vec![1]))
+		);
+		assert!(ChainedDelegators::<Test>::contains_key(10, 11));
+		assert!(ChainedDelegators::<Test>::contains_key(11, 3));
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 100);
+
+		// Rewards flow to the end of the chain.
+		author_every_slot(1);
+		next_block();
+		dpos_events();
+		assert_ok!(Dpos::claim_rewards(RuntimeOrigin::signed(3)));
+		assert_eq!(dpos_events(), vec![Event::Rewarded { who: 3, validator: 1, amount: 40 }]);
+
+		// And so do slashes.
+		<Dpos as DoSlash<Test>>::do_slash(1, Perbill::from_percent(10));
+		assert_eq!(held(HoldReason::Delegation, 10), 18);
+		assert_eq!(held(HoldReason::Delegation, 11), 27);
+		assert_eq!(held(HoldReason::Delegation, 3), 36);
+	});
+}
+
+#[test]
+fn delegation_chains_are_bounded() {
+	StateBuilder::default().with_balance(12, 100).build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 10, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 20));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 10, 20));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(3), 11, 20));
+
+		assert_noop!(
+			Dpos::delegate(RuntimeOrigin::signed(12), 3, 20),
+			Error::<Test>::DelegationTooDeep
+		);
+		assert_noop!(
+			Dpos::nominate(RuntimeOrigin::signed(3), vec![3]),
+			Error::<Test>::DelegationCycle
+		);
+
+		// Delegators in the middle of a chain are stuck with their validator.
+		assert_noop!(
+			Dpos::nominate(RuntimeOrigin::signed(11), vec![2]),
+			Error::<Test>::HasChainedDelegators
+		);
+		assert_noop!(
+			Dpos::undelegate(RuntimeOrigin::signed(11)),
+			Error::<Test>::HasChainedDelegators
+		);
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(11), 10));
+
+		// Until the chain behind them is gone.
+		assert_ok!(Dpos::nominate(RuntimeOrigin::signed(3), vec![2]));
+		assert!(!ChainedDelegators::<Test>::contains_key(11, 3));
+		assert_ok!(Dpos::nominate(RuntimeOrigin::signed(11), vec![2]));
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 30);
+		assert_eq!(Validators::<Test>::get(2).unwrap().total_backing, 40);
+	});
+}
+
+#[test]
+fn phragmen_spreads_stake_over_winners() {
+	use crate::election::{ElectionStrategy, SequentialPhragmen, TopBacking};
+
+	// 10 backs both 1 and 2 with a lot of stake, 11 only backs 3.
+	let voters = || vec![(10, 100, vec![1, 2]), (11, 80, vec![3])];
+	let winners = |supports: crate::election::Supports<AccountId>| {
+		supports
+			.into_iter()
+			.map(|(who, support)| (who, support.total))
+			.collect::<Vec<_>>()
+	};
+
+	// Counting the stake of 10 twice keeps 3 out.
+	let top = TopBacking::elect(2, vec![1, 2, 3], voters()).unwrap();
+	assert_eq!(winners(top), vec![(1, 100), (2, 100)]);
+
+	// Spreading it lets 3 in, and all of the stake of 10 goes to 1.
+	let phragmen = SequentialPhragmen::<Perbill>::elect(2, vec![1, 2, 3], voters()).unwrap();
+	assert_eq!(winners(phragmen), vec![(1, 100), (3, 80)]);
+}
+
+#[test]
+fn offchain_worker_submits_a_solution() {
+	StateBuilder::default().build_offchain_and_execute(|pool| {
+		use codec::Decode;
+		use frame_support::{
+			pallet_prelude::TransactionSource,
+			traits::{Hooks, UnfilteredDispatchable},
+			unsigned::ValidateUnsigned,
+		};
+
+		for who in [1, 2, 3] {
+			assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(who), 10, Perbill::zero()));
+		}
+		// 10 backs both 1 and 2 with a lot of stake, 11 only backs 3.
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 80));
+		assert_ok!(Dpos::nominate(RuntimeOrigin::signed(10), vec![1, 2]));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 3, 60));
+		MaxValidators::set(2);
+
+		// Nothing to mine before the snapshot.
+		run_to_block(88);
+		Dpos::offchain_worker(88);
+		assert!(pool.read().transactions.is_empty());
+
+		run_to_block(90);
+		let snapshot = Dpos::snapshot().unwrap();
+		assert_eq!(snapshot.epoch, 1);
+		let elected_by = |phragmen| {
+			UsePhragmen::set(phragmen);
+			let solution = Dpos::mine_solution(&snapshot)?;
+			let mut winners = solution
+				.winners
+				.iter()
+				.map(|&i| snapshot.candidates[i as usize])
+				.collect::<Vec<_>>();
+			winners.sort();
+			Some(winners)
+		};
+		// `TopBacking` counts the stake of 10 twice, and scaled down to be feasible it does worse
+		// than the fallback, which splits it evenly between 1 and 2.
+		assert_eq!(elected_by(false), None);
+		assert_eq!(elected_by(true), Some(vec![1, 3]));
+
+		Dpos::offchain_worker(90);
+		let tx = pool.write().transactions.pop().unwrap();
+		let tx = Extrinsic::decode(&mut &*tx).unwrap();
+		let RuntimeCall::Dpos(call) = tx.call else { panic!("unexpected call") };
+		assert!(Dpos::validate_unsigned(TransactionSource::Local, &call).is_ok());
+		assert!(Dpos::validate_unsigned(TransactionSource::External, &call).is_err());
+		assert_ok!(call.dispatch_bypass_filter(RuntimeOrigin::none()));
+
+		// A solution is only mined once.
+		Dpos::offchain_worker(91);
+		assert!(pool.read().transactions.is_empty());
+
+		// The solution beats the fallback, which would have elected 3 and one of 1 and 2, both
+		// backed by only half of the stake of 10.
+		run_to_block(100);
+		let mut reported = ReportedValidators::get();
+		reported.sort();
+		assert_eq!(reported, vec![1, 3]);
+		// The stake of 10 is split the way the solution distributes it, all of it behind 1.
+		let stakes = Delegations::<Test>::get(10)
+			.unwrap()
+			.nominations
+			.iter()
+			.map(|nomination| (nomination.validator, nomination.stake))
+			.collect::<Vec<_>>();
+		assert_eq!(stakes, vec![(1, 80), (2, 0)]);
+		assert_eq!(Exposures::<Test>::get(1, 1).unwrap().total, 90);
+		assert_eq!(Validators::<Test>::get(2).unwrap().total_backing, 10);
+		assert!(Dpos::snapshot().is_none());
+		assert!(QueuedSolution::<Test>::get().is_none());
+	});
+}
+
+#[test]
+fn snapshot_is_taken_in_pages() {
+	StateBuilder::default()
+		.with_balance(12, 100)
+		.with_balance(13, 100)
+		.with_balance(14, 100)
+		.build_and_execute(|| {
+			assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+			assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 10, Perbill::zero()));
+			for delegator in [10, 11, 12, 13] {
+				assert_ok!(Dpos::delegate(RuntimeOrigin::signed(delegator), 1, 10));
+			}
+			// Only as many delegators as the pages can visit are allowed.
+			assert_eq!(Dpos::max_delegators(), 4);
+			assert_noop!(
+				Dpos::delegate(RuntimeOrigin::signed(14), 1, 10),
+				Error::<Test>::TooManyDelegators
+			);
+
+			// The first page freezes the candidates, and has their self votes.
+			run_to_block(88);
+			assert!(matches!(
+				SnapshotProgress::<Test>::get(),
+				Some(SnapshotStatus::Ongoing { pages: 1, .. })
+			));
+			let first = SnapshotVoters::<Test>::get(0).unwrap();
+			assert_eq!(first.len(), 2 + 2);
+			assert_eq!(first[..2], [(1, 10, vec![1]), (2, 10, vec![2])]);
+			assert!(Dpos::snapshot().is_none());
+
+			// Changes to delegators already in the snapshot are not picked up, and neither are new
+			// candidates.
+			let (moved, _, _) = first[2].clone();
+			assert_ok!(Dpos::nominate(RuntimeOrigin::signed(moved), vec![2]));
+			assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(3), 10, Perbill::zero()));
+
+			run_to_block(89);
+			assert_eq!(
+				SnapshotProgress::<Test>::get(),
+				Some(SnapshotStatus::Complete { pages: 2 })
+			);
+			let snapshot = Dpos::snapshot().unwrap();
+			assert_eq!(snapshot.candidates, vec![1, 2]);
+			// Every delegator is in the snapshot.
+			assert_eq!(snapshot.voters.len(), 2 + 4);
+			let mut delegators =
+				snapshot.voters[2..].iter().map(|(who, _, _)| *who).collect::<Vec<_>>();
+			delegators.sort();
+			delegators.dedup();
+			assert_eq!(delegators.len(), 4);
+			assert!(snapshot.voters.contains(&(moved, 10, vec![1])));
+
+			run_to_block(100);
+			assert!(SnapshotProgress::<Test>::get().is_none());
+			assert!(SnapshotTargets::<Test>::get().is_none());
+			assert_eq!(SnapshotVoters::<Test>::iter().count(), 0);
+		});
+}
+
+#[test]
+fn snapshot_only_has_counted_nominations() {
+	MaxDelegatorsPerValidator::set(1);
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 20));
+		// Oversubscribed with 1, but counted with 2.
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 20));
+		assert_ok!(Dpos::nominate(RuntimeOrigin::signed(11), vec![1, 2]));
+		assert_eq!(Dpos::oversubscribed(&11), vec![1]);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 40);
+		assert_eq!(Validators::<Test>::get(2).unwrap().total_backing, 30);
+
+		run_to_block(89);
+		let snapshot = Dpos::snapshot().unwrap();
+		assert_eq!(snapshot.candidates, vec![1, 2]);
+		assert_eq!(snapshot.backing, vec![40, 30]);
+		// 11 only votes with what backs 2.
+		let mut voters = snapshot.voters;
+		voters.sort();
+		assert_eq!(
+			voters,
+			vec![(1, 20, vec![1]), (2, 20, vec![2]), (10, 20, vec![1]), (11, 10, vec![2])]
+		);
+		let backing = |validator| {
+			voters
+				.iter()
+				.filter(|(_, _, targets)| targets.contains(&validator))
+				.map(|(_, stake, _)| stake)
+				.sum::<u64>()
+		};
+		assert_eq!((backing(1), backing(2)), (40, 30));
+
+		// The election leaves the oversubscribed nomination as it is.
+		run_to_block(100);
+		let delegation = Delegations::<Test>::get(11).unwrap();
+		assert_eq!(
+			delegation
+				.nominations
+				.iter()
+				.map(|n| (n.validator, n.stake))
+				.collect::<Vec<_>>(),
+			vec![(1, 10), (2, 10)]
+		);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 40);
+	});
+}
+
+#[test]
+fn submitted_solutions_are_verified() {
+	use crate::election::{ElectionScore, RawSolution};
+
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 10, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(3), 30, Perbill::zero()));
+
+		// Elect all of them, each backed by its own stake.
+		let score = ElectionScore { minimal_stake: 10, sum_stake: 60, sum_stake_squared: 1400 };
+		let assignments = vec![(0, vec![(0, 30)]), (1, vec![(1, 20)]), (2, vec![(2, 10)])];
+		let solution = RawSolution { epoch: 1, winners: vec![0, 1, 2], assignments, score };
+		let submit =
+			|solution: &RawSolution| Dpos::submit_solution(RuntimeOrigin::none(), solution.clone());
+
+		assert_noop!(submit(&solution), Error::<Test>::ElectionClosed);
+		run_to_block(90);
+		// The snapshot is sorted by backing, so 1 comes last.
+		let snapshot = Dpos::snapshot().unwrap();
+		assert_eq!(snapshot.candidates, vec![3, 2, 1]);
+		assert_eq!(snapshot.voters[2], (1, 10, vec![1]));
+
+		assert_noop!(
+			Dpos::submit_solution(RuntimeOrigin::signed(1), solution.clone()),
+			sp_runtime::DispatchError::BadOrigin
+		);
+		assert_noop!(
+			submit(&RawSolution { epoch: 2, ..solution.clone() }),
+			Error::<Test>::ElectionClosed
+		);
+		assert_noop!(
+			submit(&RawSolution { score: Default::default(), ..solution.clone() }),
+			Error::<Test>::WrongScore
+		);
+		let with_assignments = |assignments: Vec<(u32, Vec<(u32, u64)>)>| RawSolution {
+			assignments,
+			..solution.clone()
+		};
+		// Assigning more than the stake of a voter.
+		assert_noop!(
+			submit(&with_assignments(vec![
+				(0, vec![(0, 30)]),
+				(1, vec![(1, 20)]),
+				(2, vec![(2, 11)])
+			])),
+			Error::<Test>::InfeasibleSolution
+		);
+		// Assigning a voter to a candidate it does not back.
+		assert_noop!(
+			submit(&with_assignments(vec![
+				(0, vec![(0, 30)]),
+				(1, vec![(2, 20)]),
+				(2, vec![(2, 10)])
+			])),
+			Error::<Test>::InfeasibleSolution
+		);
+		// Electing the same candidate twice.
+		assert_noop!(
+			submit(&RawSolution { winners: vec![0, 1, 1], ..solution.clone() }),
+			Error::<Test>::InfeasibleSolution
+		);
+		// Leaving seats empty, even with a correct score.
+		let lone = ElectionScore { minimal_stake: 10, sum_stake: 10, sum_stake_squared: 100 };
+		assert_noop!(
+			submit(&RawSolution {
+				winners: vec![2],
+				assignments: vec![(2, vec![(2, 10)])],
+				score: lone,
+				..solution.clone()
+			}),
+			Error::<Test>::InfeasibleSolution
+		);
+		// Leaving out stake, so that the fallback does better.
+		let weak = ElectionScore { minimal_stake: 10, sum_stake: 40, sum_stake_squared: 600 };
+		assert_noop!(
+			submit(&RawSolution {
+				assignments: vec![(0, vec![(0, 10)]), (1, vec![(1, 20)]), (2, vec![(2, 10)])],
+				score: weak,
+				..solution.clone()
+			}),
+			Error::<Test>::WeakSolution
+		);
+
+		assert_ok!(submit(&solution));
+		assert_eq!(dpos_events().last(), Some(&Event::SolutionStored { epoch: 1, score }));
+		assert_noop!(submit(&solution), Error::<Test>::WeakSolution);
+
+		// A winner that is no longer a candidate by the election is left out.
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(2)));
+		run_to_block(100);
+		assert_eq!(ReportedValidators::get(), vec![3, 1]);
+	});
+}
+
+#[test]
+fn nominations_split_the_delegation() {
+	StateBuilder::default().build_and_execute(|| {
+		for who in [1, 2, 3] {
+			assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(who), 10, Perbill::zero()));
+		}
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 31));
+
+		assert_noop!(Dpos::nominate(RuntimeOrigin::signed(10), vec![]), Error::<Test>::NoTargets);
+		assert_noop!(
+			Dpos::nominate(RuntimeOrigin::signed(10), vec![1, 2, 1]),
+			Error::<Test>::DuplicateTarget
+		);
+		assert_noop!(
+			Dpos::nominate(RuntimeOrigin::signed(10), vec![1, 2, 3, 4]),
+			Error::<Test>::TooManyTargets
+		);
+		assert_noop!(
+			Dpos::nominate(RuntimeOrigin::signed(10), vec![1, 4]),
+			Error::<Test>::NotValidator
+		);
+
+		// The remainder goes to the first validators.
+		assert_ok!(Dpos::nominate(RuntimeOrigin::signed(10), vec![1, 2, 3]));
+		let delegation = Delegations::<Test>::get(10).unwrap();
+		assert_eq!(delegation.amount, 31);
+		assert_eq!(
+			delegation
+				.nominations
+				.iter()
+				.map(|n| (n.validator, n.stake))
+				.collect::<Vec<_>>(),
+			vec![(1, 11), (2, 10), (3, 10)]
+		);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 21);
+		assert_eq!(Validators::<Test>::get(2).unwrap().total_backing, 20);
+		assert_eq!(Validators::<Test>::get(3).unwrap().total_backing, 20);
+
+		// Rewards are earned on the stake behind the block author only.
+		author_every_slot(2);
+		next_block();
+		assert_eq!(Dpos::pending_rewards(&10), 50);
+
+		// Slashes only hit the stake behind the offender.
+		<Dpos as DoSlash<Test>>::do_slash(3, Perbill::from_percent(50));
+		assert_eq!(held(HoldReason::Delegation, 10), 26);
+		assert_eq!(Delegations::<Test>::get(10).unwrap().amount, 26);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 21);
+
+		// Withdrawing takes from every nomination.
+		assert_ok!(Dpos::decrease_delegation(RuntimeOrigin::signed(10), 13));
+		let chunks = Unlocking::<Test>::get(10);
+		assert_eq!(chunks.iter().map(|c| c.validator).collect::<Vec<_>>(), vec![1, 2, 3]);
+		assert_eq!(chunks.iter().map(|c| c.value).sum::<Balance>(), 13);
+		assert_eq!(Delegations::<Test>::get(10).unwrap().amount, 13);
+	});
+}
+
+#[test]
+fn migrate_v0_to_v1_works() {
+	use frame_support::traits::{GetStorageVersion, OnRuntimeUpgrade, StorageVersion};
+	use migrations::v1::{v0, MigrateV0ToV1};
+
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		Validators::<Test>::mutate(1, |info| {
+			info.as_mut().unwrap().reward_per_share = FixedU128::from_u32(2)
+		});
+		StorageVersion::new(0).put::<Dpos>();
+		v0::Delegations::<Test>::insert(10, v0::DelegationInfo::<Test> { who: 1, amount: 30 });
+		// Only candidates can be backed, and only with what can be held.
+		v0::Delegations::<Test>::insert(11, v0::DelegationInfo::<Test> { who: 3, amount: 30 });
+		v0::Delegations::<Test>::insert(2, v0::DelegationInfo::<Test> { who: 1, amount: 500 });
+
+		MigrateV0ToV1::<Test>::on_runtime_upgrade();
+
+		assert_eq!(Dpos::on_chain_storage_version(), 1);
+		let delegation = Delegations::<Test>::get(10).unwrap();
+		assert_eq!(delegation.targets.to_vec(), vec![1]);
+		assert_eq!(delegation.amount, 30);
+		assert_eq!(
+			delegation.nominations.to_vec(),
+			vec![Nomination {
+				validator: 1,
+				stake: 30,
+				reward_per_share_paid: FixedU128::from_u32(2)
+			}]
+		);
+		assert_eq!(held(HoldReason::Delegation, 10), 30);
+		assert!(DelegatorsOf::<Test>::contains_key(1, 10));
+		assert!(!Delegations::<Test>::contains_key(11));
+		assert!(!Delegations::<Test>::contains_key(2));
+		assert_eq!(held(HoldReason::Delegation, 2), 0);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 50);
+
+		// Running it again is a no-op.
+		MigrateV0ToV1::<Test>::on_runtime_upgrade();
+		assert_eq!(Delegations::<Test>::get(10).unwrap(), delegation);
+
+		// The candidate list, the counted delegators and the count of delegations are rebuilt by
+		// the later migrations.
+		CountedDelegators::<Test>::insert(1, BoundedVec::truncate_from(vec![(10, 30)]));
+		CandidateList::<Test>::set(&1, Some(50));
+		Delegations::<Test>::initialize_counter();
+	});
+}
+
+#[test]
+fn migrate_v1_to_v2_works() {
+	use frame_support::traits::{GetStorageVersion, OnRuntimeUpgrade, StorageVersion};
+	use migrations::v2::{v1, MigrateV1ToV2};
+
+	new_test_ext().execute_with(|| {
+		StorageVersion::new(1).put::<Dpos>();
+		v1::TopValidators::<Test>::put(vec![1, 2, 3]);
+		let info = |self_stake, total_backing| ValidatorInfo::<Test> {
+			self_stake,
+			total_backing,
+			..Default::default()
+		};
+		Validators::<Test>::insert(1, info(10, 40));
+		Validators::<Test>::insert(2, info(20, 25));
+		// Unregistered, but still delegated to.
+		Validators::<Test>::insert(3, info(0, 30));
+
+		MigrateV1ToV2::<Test>::on_runtime_upgrade();
+
+		assert_eq!(Dpos::on_chain_storage_version(), 2);
+		assert_eq!(candidate_list(), vec![(1, 40), (2, 25)]);
+		assert!(!v1::TopValidators::<Test>::exists());
+	});
+}
+
+#[test]
+fn migrate_v2_to_v3_works() {
+	use frame_support::traits::{GetStorageVersion, OnRuntimeUpgrade, StorageVersion};
+	use migrations::v3::MigrateV2ToV3;
+
+	StateBuilder::default().with_balance(12, 100).build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 30));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(12), 1, 10));
+		author_every_slot(1);
+		run_to_block(2);
+		dpos_events();
+
+		// Every delegator counted, as before the cap.
+		StorageVersion::new(2).put::<Dpos>();
+		CountedDelegators::<Test>::remove(1);
+		Validators::<Test>::mutate(1, |info| info.as_mut().unwrap().total_backing = 110);
+		MaxDelegatorsPerValidator::set(2);
+
+		MigrateV2ToV3::<Test>::on_runtime_upgrade();
+
+		assert_eq!(Dpos::on_chain_storage_version(), 3);
+		assert_eq!(CountedDelegators::<Test>::get(1).to_vec(), vec![(10, 50), (11, 30)]);
+		assert_eq!(Validators::<Test>::get(1).unwrap().total_backing, 100);
+		assert_eq!(candidate_list(), vec![(1, 100)]);
+		// The oversubscribed delegator is paid what it earned before the upgrade.
+		assert_eq!(dpos_events(), vec![Event::Rewarded { who: 12, validator: 1, amount: 9 }]);
+		assert_eq!(Dpos::oversubscribed(&12), vec![1]);
+	});
+}
+
+#[test]
+fn migrate_v3_to_v4_works() {
+	use frame_support::traits::{GetStorageVersion, OnRuntimeUpgrade, StorageVersion};
+	use migrations::v4::MigrateV3ToV4;
+
+	#[frame_support::storage_alias]
+	type CounterForDelegations = StorageValue<Dpos, u32>;
+
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(11), 1, 30));
+
+		// The delegations were not counted before.
+		StorageVersion::new(3).put::<Dpos>();
+		CounterForDelegations::kill();
+		assert_eq!(Delegations::<Test>::count(), 0);
+
+		MigrateV3ToV4::<Test>::on_runtime_upgrade();
+
+		assert_eq!(Dpos::on_chain_storage_version(), 4);
+		assert_eq!(Delegations::<Test>::count(), 2);
+	});
+}
+
+#[test]
+fn migrate_v4_to_v5_works() {
+	use frame_support::traits::{GetStorageVersion, OnRuntimeUpgrade, StorageVersion};
+	use migrations::v5::MigrateV4ToV5;
+
+	StateBuilder::default().build_and_execute(|| {
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(1), 20, Perbill::zero()));
+		assert_ok!(Dpos::register_validator(RuntimeOrigin::signed(2), 20, Perbill::zero()));
+		assert_ok!(Dpos::delegate(RuntimeOrigin::signed(10), 1, 50));
+		assert_ok!(Dpos::nominate(RuntimeOrigin::signed(10), vec![1, 2]));
+		assert_ok!(Dpos::undelegate(RuntimeOrigin::signed(10)));
+		assert_ok!(Dpos::unregister_validator(RuntimeOrigin::signed(1)));
+
+		// Unbonding stake was not indexed before.
+		StorageVersion::new(4).put::<Dpos>();
+		let _ = UnbondingFrom::<Test>::clear(u32::MAX, None);
+
+		MigrateV4ToV5::<Test>::on_runtime_upgrade();
+
+		assert_eq!(Dpos::on_chain_storage_version(), 5);
+		let mut indexed = UnbondingFrom::<Test>::iter_keys().collect::<Vec<_>>();
+		indexed.sort();
+		assert_eq!(indexed, vec![(1, 1), (1, 10), (2, 10)]);
+	});
+}
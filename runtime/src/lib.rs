@@ -18,10 +18,14 @@ use frame::{
 		},
 		prelude::*,
 	},
-	traits::{FindAuthor, One},
+	traits::One,
 };
 use pallet_transaction_payment::{ConstFeeMultiplier, FeeDetails, Multiplier, RuntimeDispatchInfo};
-use sp_runtime::traits::Convert;
+use sp_runtime::{
+	impl_opaque_keys,
+	traits::{Convert, ConvertInto, OpaqueKeys},
+	Perbill,
+};
 
 #[runtime_version]
 const VERSION: RuntimeVersion = RuntimeVersion {
@@ -86,6 +90,12 @@ mod runtime {
 	#[runtime::pallet_index(8)]
 	pub type Treasury = pallet_treasury;
 
+	#[runtime::pallet_index(9)]
+	pub type RandomnessCollectiveFlip = pallet_insecure_randomness_collective_flip;
+
+	#[runtime::pallet_index(10)]
+	pub type Session = pallet_session;
+
 	#[runtime::pallet_index(99)]
 	pub type Timestamp = pallet_timestamp;
 }
@@ -245,48 +255,132 @@ impl pallet_free_tx::Config for Runtime {
 
 parameter_types! {
 	pub const MaxValidators: u32 = 10;
+	// Stakers are rewarded by the inflation curve below rather than per block.
+	pub const BlockReward: Balance = 0;
+	// Between 2.5% and 10% of yearly inflation for validators, the most with half of the issuance
+	// staked. The rest of the 10% is not minted, see `RewardRemainder`.
+	pub const MinInflation: Perbill = Perbill::from_parts(25_000_000);
+	pub const MaxInflation: Perbill = Perbill::from_percent(10);
+	pub const IdealStake: Perbill = Perbill::from_percent(50);
+	pub const Falloff: Perbill = Perbill::from_percent(25);
+	// Epochs of 100 blocks of 6 seconds.
+	pub const EpochsPerYear: u32 = 52_596;
+	pub const MaxCommissionChange: Perbill = Perbill::from_percent(5);
+	pub const UnbondingPeriod: u32 = 7;
+	pub const MinValidatorBond: Balance = 10_000;
+	pub const MinDelegation: Balance = 100;
+	pub const DelegationDeposit: Balance = 100;
+	pub const MaxDelegatorsPerValidator: u32 = 256;
+	// Validators missing more than half of their slots in an epoch are chilled, and slashed up to
+	// 10% for missing all of them.
+	pub const MissedSlotsThreshold: Perbill = Perbill::from_percent(50);
+	pub const MissedSlotsSlash: Perbill = Perbill::from_percent(10);
+	// Slashes wait for two full epochs, giving governance time to cancel erroneous ones.
+	pub const SlashDeferDuration: u32 = 2;
+	// Exposures are kept for a day of epochs, for payouts to be claimed and slashes applied.
+	pub const HistoryDepth: u32 = 144;
+	// Bags growing by a factor of 10, up to a bag for everyone with more than 10^15.
+	pub const BagThresholds: &'static [Balance] = &[
+		10,
+		100,
+		1_000,
+		10_000,
+		100_000,
+		1_000_000,
+		10_000_000,
+		100_000_000,
+		1_000_000_000,
+		10_000_000_000,
+		100_000_000_000,
+		1_000_000_000_000,
+		10_000_000_000_000,
+		100_000_000_000_000,
+		1_000_000_000_000_000,
+	];
 }
 
-pub struct BlockAuthor;
-impl FindAuthor<AccountId> for BlockAuthor {
-	fn find_author<'a, I>(_: I) -> Option<AccountId>
-	where
-		I: 'a + IntoIterator<Item = ([u8; 4], &'a [u8])>,
-	{
-		// return a random-ish block author previously reported.
-		let last_known_set = ValidatorSet::get();
-		if last_known_set.is_empty() {
-			return None;
-		}
+impl pallet_insecure_randomness_collective_flip::Config for Runtime {}
 
-		let index = frame_system::Pallet::<Runtime>::block_number() as usize % last_known_set.len();
-		Some(last_known_set[index].clone())
+impl_opaque_keys! {
+	pub struct SessionKeys {
+		pub dpos: Dpos,
 	}
 }
 
-parameter_types! {
-	// This is a temporary storage that will keep the validators. In reality, this would have been
-	// `pallet-aura` or another pallet that would consume these.
-	pub storage ValidatorSet: Vec<AccountId> = vec![];
-}
-
-pub struct StoreNewValidatorSet;
-impl pallet_dpos::ReportNewValidatorSet<AccountId> for StoreNewValidatorSet {
-	fn report_new_validator_set(new_set: Vec<AccountId>) {
-		ValidatorSet::set(&new_set);
-	}
+/// Sessions last one epoch of pallet-dpos, and start on the same block as the epochs, right after
+/// the election. The newly elected validators are thus queued for the next session, which is why
+/// pallet-dpos elects ahead, and only activates them along with that session.
+impl pallet_session::Config for Runtime {
+	type RuntimeEvent = RuntimeEvent;
+	type ValidatorId = AccountId;
+	type ValidatorIdOf = ConvertInto;
+	type ShouldEndSession = pallet_session::PeriodicSessions<ConstU32<100>, ConstU32<0>>;
+	type NextSessionRotation = pallet_session::PeriodicSessions<ConstU32<100>, ConstU32<0>>;
+	type SessionManager = Dpos;
+	type SessionHandler = <SessionKeys as OpaqueKeys>::KeyTypeIdProviders;
+	type Keys = SessionKeys;
+	type WeightInfo = pallet_session::weights::SubstrateWeight<Runtime>;
 }
 
 /// Configure the pallet-dpos in pallets/dpos.
 impl pallet_dpos::Config for Runtime {
 	type RuntimeEvent = RuntimeEvent;
 	type NativeBalance = Balances;
+	type RuntimeHoldReason = RuntimeHoldReason;
 	type MaxValidators = MaxValidators;
-	type FindAuthor = BlockAuthor;
-	type ReportNewValidatorSet = StoreNewValidatorSet;
+	// Blocks are produced without consensus, and carry no digest naming their author, so each one
+	// is credited to the validator of its slot. Nobody misses a slot this way; switch to
+	// `pallet_dpos::DigestAuthor` once block authoring adds the `dpos` pre-runtime digest.
+	type FindAuthor = pallet_dpos::SlotAuthor<Runtime>;
+	type Randomness = RandomnessCollectiveFlip;
+	type StakeWeightedSlots = ConstBool<true>;
+	type ReportNewValidatorSet = ();
+	type ElectAhead = ConstBool<true>;
+	type ValidatorRegistration = Session;
 	// Assuming blocks happen every 6 seconds, this will be 600 seconds, approximately 10 minutes.
 	// But this is all just test config, but gives you an idea how this is all CONFIGURABLE
 	type EpochDuration = ConstU32<100>;
+	type BlockReward = BlockReward;
+	type EraPayout = pallet_dpos::payout::IdealStakingCurve<
+		MinInflation,
+		MaxInflation,
+		IdealStake,
+		Falloff,
+		EpochsPerYear,
+	>;
+	// `pallet_treasury` keeps no funds of its own, so there is no account to send the rest of the
+	// inflation to, and it is not minted. Resolve it into the treasury once it has an account.
+	type RewardRemainder = ();
+	type MaxCommissionChange = MaxCommissionChange;
+	type UnbondingPeriod = UnbondingPeriod;
+	type MinValidatorBond = MinValidatorBond;
+	type MinDelegation = MinDelegation;
+	type DelegationDeposit = DelegationDeposit;
+	type MaxDelegatorsPerValidator = MaxDelegatorsPerValidator;
+	type MaxUnlockingChunks = ConstU32<32>;
+	type OnSlash = ();
+	type MaxDelegationDepth = ConstU32<3>;
+	type MaxNominations = ConstU32<16>;
+	type ElectionStrategy = pallet_dpos::election::SequentialPhragmen;
+	type UnsignedPhase = ConstU32<10>;
+	type SnapshotPages = ConstU32<8>;
+	type VotersPerPage = ConstU32<512>;
+	type MaxElectableCandidates = ConstU32<100>;
+	type BagThresholds = BagThresholds;
+	type UnsignedPriority = ConstU64<{ TransactionPriority::MAX / 2 }>;
+	type MissedSlotsThreshold = MissedSlotsThreshold;
+	type MissedSlotsSlash = MissedSlotsSlash;
+	type SlashDeferDuration = SlashDeferDuration;
+	type HistoryDepth = HistoryDepth;
+	type SlashCancelOrigin = EnsureRoot<AccountId>;
+}
+
+impl<C> frame_system::offchain::SendTransactionTypes<C> for Runtime
+where
+	RuntimeCall: From<C>,
+{
+	type OverarchingCall = RuntimeCall;
+	type Extrinsic = <Block as sp_runtime::traits::Block>::Extrinsic;
 }
 
 // In a real blockchain, this wouldn't be some simple function, but actually a whole pallet that
@@ -343,8 +437,23 @@ type Nonce = <Runtime as frame_system::Config>::Nonce;
 type Block = frame::runtime::types_common::BlockOf<Runtime, SignedExtra>;
 type Header = HeaderFor<Runtime>;
 
-type RuntimeExecutive =
-	Executive<Runtime, Block, frame_system::ChainContext<Runtime>, Runtime, AllPalletsWithSystem>;
+/// Migrations to apply on runtime upgrade.
+type Migrations = (
+	pallet_dpos::migrations::v1::MigrateV0ToV1<Runtime>,
+	pallet_dpos::migrations::v2::MigrateV1ToV2<Runtime>,
+	pallet_dpos::migrations::v3::MigrateV2ToV3<Runtime>,
+	pallet_dpos::migrations::v4::MigrateV3ToV4<Runtime>,
+	pallet_dpos::migrations::v5::MigrateV4ToV5<Runtime>,
+);
+
+type RuntimeExecutive = Executive<
+	Runtime,
+	Block,
+	frame_system::ChainContext<Runtime>,
+	Runtime,
+	AllPalletsWithSystem,
+	Migrations,
+>;
 
 #[cfg(feature = "runtime-benchmarks")]
 mod benches {
@@ -428,14 +537,14 @@ impl_runtime_apis! {
 	}
 
 	impl apis::SessionKeys<Block> for Runtime {
-		fn generate_session_keys(_seed: Option<Vec<u8>>) -> Vec<u8> {
-			Default::default()
+		fn generate_session_keys(seed: Option<Vec<u8>>) -> Vec<u8> {
+			SessionKeys::generate(seed)
 		}
 
 		fn decode_session_keys(
-			_encoded: Vec<u8>,
+			encoded: Vec<u8>,
 		) -> Option<Vec<(Vec<u8>, apis::KeyTypeId)>> {
-			Default::default()
+			SessionKeys::decode_into_raw_public_keys(&encoded)
 		}
 	}
 